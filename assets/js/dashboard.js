@@ -86,15 +86,22 @@ function copyRedirectKeyToClipboard (element, event) {
   }
 
   if (event.detail.successful === true) {
+    const key = element.querySelector('#redirectKeyInput').value
+
     // copy the redirect key from the textbox into the clipboard
     try {
-      const text = element.querySelector('#redirectKeyInput').value
-      navigator.clipboard.writeText(text)
+      navigator.clipboard.writeText(key)
     } catch (err) {
       console.error('Failed to copy: ', err)
     }
     toast(true, 'Shortend URL copied to clipboard')
 
+    // offer the QR code of the new link for download
+    const qrButton = document.getElementById('qrDownloadButton')
+    qrButton.href = `/api/redirect/${encodeURIComponent(key)}/qr?format=png&size=512`
+    qrButton.download = `${key}.png`
+    qrButton.classList.remove('hidden')
+
     // clear the URL input field and refresh the redirect key input
     element.querySelector('input[name="url"]').value = ''
     htmx.trigger('#randomizeButton', 'click')
@@ -0,0 +1,141 @@
+use reqwest::{Method, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shidou::types::{DeleteRedirectInput, RedirectInput};
+
+use crate::{config::Resolved, error::CliError};
+
+/// A redirect as returned by the API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Redirect {
+    pub key: String,
+    pub url: String,
+    pub redirect_host: String,
+    pub visits: u64,
+    #[serde(default)]
+    pub interstitial: bool,
+    pub og_title: Option<String>,
+    pub og_description: Option<String>,
+    pub og_image: Option<String>,
+    pub created_utc: String,
+    pub updated_utc: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CountryCount {
+    pub country: Option<String>,
+    pub visits: u64,
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    config: Resolved,
+}
+
+impl Client {
+    pub fn new(config: Resolved) -> Result<Client, CliError> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("shidou-cli/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        Ok(Client { http, config })
+    }
+
+    pub fn short_url(&self, key: &str) -> String {
+        format!("{}/{}", self.config.url, key)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/api{}", self.config.url, path))
+            .bearer_auth(&self.config.token)
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, CliError> {
+        let response = request.send().await?;
+        Ok(check_status(response).await?.json::<T>().await?)
+    }
+
+    pub async fn list(&self) -> Result<Vec<Redirect>, CliError> {
+        self.send(self.request(Method::GET, "/redirect")).await
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Redirect, CliError> {
+        self.send(self.request(Method::GET, &redirect_path(key, "")))
+            .await
+    }
+
+    pub async fn create(&self, input: &RedirectInput) -> Result<Redirect, CliError> {
+        self.send(self.request(Method::POST, "/redirect").json(input))
+            .await
+    }
+
+    pub async fn update(&self, input: &RedirectInput) -> Result<Redirect, CliError> {
+        self.send(self.request(Method::PUT, "/redirect").json(input))
+            .await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), CliError> {
+        let input = DeleteRedirectInput {
+            key: key.to_string(),
+        };
+        let response = self
+            .request(Method::DELETE, "/redirect")
+            .json(&input)
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    pub async fn countries(&self, key: &str) -> Result<Vec<CountryCount>, CliError> {
+        self.send(self.request(Method::GET, &redirect_path(key, "/countries")))
+            .await
+    }
+}
+
+/// `/redirect/{key}` followed by `rest`, with the key percent-encoded as a single path segment
+fn redirect_path(key: &str, rest: &str) -> String {
+    let mut url = reqwest::Url::parse("http://localhost/redirect").expect("base url is valid");
+    url.path_segments_mut()
+        .expect("base url has a path")
+        .push(key);
+    format!("{}{}", url.path(), rest)
+}
+
+/// Turn error responses into a `CliError`, using the `error` message of the body when there is one
+async fn check_status(response: Response) -> Result<Response, CliError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body["error"].as_str().map(|error| error.to_string()))
+        .unwrap_or_else(|| match body.is_empty() {
+            true => status
+                .canonical_reason()
+                .unwrap_or("Request failed")
+                .to_string(),
+            false => body,
+        });
+
+    Err(CliError::Api { status, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redirect_path;
+
+    #[test]
+    fn encodes_the_key_as_one_segment() {
+        assert_eq!(redirect_path("abc", ""), "/redirect/abc");
+        assert_eq!(
+            redirect_path("a/b", "/countries"),
+            "/redirect/a%2Fb/countries"
+        );
+        assert_eq!(redirect_path("a?b#c d", ""), "/redirect/a%3Fb%23c%20d");
+    }
+}
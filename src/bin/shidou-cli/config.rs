@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use crate::error::CliError;
+
+/// Settings read from `~/.config/shidou/config.toml`
+/// ```toml
+/// url = "https://shidou.example.com"
+/// token = "shd_..."
+/// ```
+/// `SHIDOU_URL` and `SHIDOU_TOKEN` take precedence over the file
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct Config {
+    pub url: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Resolved {
+    /// Base url of the instance without a trailing slash
+    pub url: String,
+    pub token: String,
+}
+
+/// `--config`, then `SHIDOU_CONFIG`, then `$XDG_CONFIG_HOME/shidou/config.toml`
+fn default_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("SHIDOU_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_home = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config_home.join("shidou").join("config.toml"))
+}
+
+pub fn load(path: Option<PathBuf>) -> Result<Resolved, CliError> {
+    let explicit = path.is_some();
+    let path = path.or_else(default_path);
+
+    let mut config = match &path {
+        Some(path) if path.exists() || explicit => {
+            let contents = std::fs::read_to_string(path).map_err(|err| {
+                CliError::Config(format!("Failed to read {}: {}", path.display(), err))
+            })?;
+            toml::from_str::<Config>(&contents).map_err(|err| {
+                CliError::Config(format!("Invalid config {}: {}", path.display(), err))
+            })?
+        }
+        _ => Config::default(),
+    };
+
+    if let Ok(url) = std::env::var("SHIDOU_URL") {
+        config.url = Some(url);
+    }
+    if let Ok(token) = std::env::var("SHIDOU_TOKEN") {
+        config.token = Some(token);
+    }
+
+    let location = path
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "the config file".to_string());
+    let url = config
+        .url
+        .filter(|url| !url.is_empty())
+        .ok_or_else(|| CliError::Config(format!("No `url` set in {} or SHIDOU_URL", location)))?;
+    let token = config
+        .token
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| {
+            CliError::Config(format!(
+                "No `token` set in {} or SHIDOU_TOKEN, create one with `POST /api/tokens`",
+                location
+            ))
+        })?;
+
+    Ok(Resolved {
+        url: url.trim_end_matches('/').to_string(),
+        token,
+    })
+}
@@ -0,0 +1,63 @@
+use std::process::ExitCode;
+
+use reqwest::StatusCode;
+
+/// Everything that can go wrong, each kind with its own exit code so scripts can tell them apart
+#[derive(Debug)]
+pub enum CliError {
+    /// Missing or malformed config, exit code 3
+    Config(String),
+    /// The server could not be reached, exit code 4
+    Connection(String),
+    /// An error response of the API, the exit code depends on the status
+    Api { status: StatusCode, message: String },
+    /// Anything else, exit code 1
+    Other(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> ExitCode {
+        let code = match self {
+            CliError::Other(_) => 1,
+            CliError::Config(_) => 3,
+            CliError::Connection(_) => 4,
+            CliError::Api { status, .. } => match *status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => 5,
+                StatusCode::NOT_FOUND => 6,
+                StatusCode::CONFLICT => 7,
+                StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => 8,
+                StatusCode::TOO_MANY_REQUESTS => 9,
+                _ => 10,
+            },
+        };
+        ExitCode::from(code)
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Config(message)
+            | CliError::Connection(message)
+            | CliError::Other(message) => {
+                write!(f, "{}", message)
+            }
+            CliError::Api { status, .. } if *status == StatusCode::UNAUTHORIZED => {
+                write!(
+                    f,
+                    "The API token was rejected, check the `token` in your config"
+                )
+            }
+            CliError::Api { status, message } => write!(f, "{} ({})", message, status),
+        }
+    }
+}
+
+impl From<reqwest::Error> for CliError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.is_connect() || err.is_timeout() {
+            true => CliError::Connection(format!("Failed to reach the server: {}", err)),
+            false => CliError::Other(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! `shidou-cli`, manage the links of a shidou instance from the terminal
+//! It talks to the HTTP API with an API token, see `config.rs` for where that is read from
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use shidou::{
+    random::generate_random_string,
+    types::{OpenGraph, RedirectInput},
+};
+
+use client::{Client, Redirect};
+use error::CliError;
+
+mod client;
+mod config;
+mod error;
+
+const KEY_LENGTH: usize = 5;
+
+#[derive(Parser)]
+#[command(version, about = "Manage the links of a shidou instance")]
+struct Cli {
+    /// Config file to use instead of ~/.config/shidou/config.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Print JSON instead of tables
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a short link
+    Shorten {
+        url: String,
+        /// Key of the short link, a random one when left out
+        #[arg(long)]
+        key: Option<String>,
+        #[command(flatten)]
+        options: LinkOptions,
+    },
+    /// List all short links
+    Ls,
+    /// Change the target or the options of a short link
+    Edit {
+        key: String,
+        /// New target url
+        #[arg(long)]
+        url: Option<String>,
+        #[command(flatten)]
+        options: LinkOptions,
+    },
+    /// Delete a short link
+    Rm { key: String },
+    /// Show the visits of a short link, by country
+    Stats { key: String },
+    /// Open a short link in the browser
+    Open {
+        key: String,
+        /// Only print the short url
+        #[arg(long)]
+        print: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct LinkOptions {
+    /// Show a preview page before sending visitors to another site
+    #[arg(long)]
+    interstitial: Option<bool>,
+    /// Title shown when the link is unfurled
+    #[arg(long)]
+    title: Option<String>,
+    /// Description shown when the link is unfurled
+    #[arg(long)]
+    description: Option<String>,
+    /// Image shown when the link is unfurled
+    #[arg(long)]
+    image: Option<String>,
+}
+
+impl LinkOptions {
+    fn apply(self, input: &mut RedirectInput) {
+        if let Some(interstitial) = self.interstitial {
+            input.interstitial = Some(interstitial);
+        }
+        if self.title.is_some() {
+            input.og.og_title = self.title;
+        }
+        if self.description.is_some() {
+            input.og.og_description = self.description;
+        }
+        if self.image.is_some() {
+            input.og.og_image = self.image;
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            err.exit_code()
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    let client = Client::new(config::load(cli.config)?)?;
+
+    match cli.command {
+        Command::Shorten { url, key, options } => {
+            let mut input = RedirectInput {
+                key: key.unwrap_or_else(|| generate_random_string(KEY_LENGTH)),
+                url,
+                interstitial: None,
+                og: OpenGraph::default(),
+            };
+            options.apply(&mut input);
+
+            let redirect = client.create(&input).await?;
+            match cli.json {
+                true => print_json(&redirect),
+                false => println!("{}", client.short_url(&redirect.key)),
+            }
+        }
+        Command::Ls => {
+            let redirects = client.list().await?;
+            match cli.json {
+                true => print_json(&redirects),
+                false => print_redirects(&redirects),
+            }
+        }
+        Command::Edit { key, url, options } => {
+            // the server keeps whatever isn't sent, only the url has to be looked up
+            let url = match url {
+                Some(url) => url,
+                None => client.get(&key).await?.url,
+            };
+            let mut input = RedirectInput {
+                key,
+                url,
+                interstitial: None,
+                og: OpenGraph::default(),
+            };
+            options.apply(&mut input);
+
+            let redirect = client.update(&input).await?;
+            match cli.json {
+                true => print_json(&redirect),
+                false => print_redirects(&[redirect]),
+            }
+        }
+        Command::Rm { key } => {
+            client.delete(&key).await?;
+            if !cli.json {
+                println!("Deleted {}", key);
+            }
+        }
+        Command::Stats { key } => {
+            let redirect = client.get(&key).await?;
+            let countries = client.countries(&key).await?;
+            match cli.json {
+                true => print_json(&serde_json::json!({
+                    "key": redirect.key,
+                    "visits": redirect.visits,
+                    "countries": countries,
+                })),
+                false => {
+                    println!("{} visits to {}", redirect.visits, redirect.key);
+                    let rows = countries
+                        .iter()
+                        .map(|c| {
+                            vec![
+                                c.country.clone().unwrap_or_else(|| "unknown".to_string()),
+                                c.visits.to_string(),
+                            ]
+                        })
+                        .collect();
+                    print_table(&["COUNTRY", "VISITS"], rows);
+                }
+            }
+        }
+        Command::Open { key, print } => {
+            // make sure the link exists before sending the browser there
+            let redirect = client.get(&key).await?;
+            let short_url = client.short_url(&redirect.key);
+            match print {
+                true => println!("{}", short_url),
+                false => open_in_browser(&short_url)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(err) => eprintln!("error: Failed to serialize output: {}", err),
+    }
+}
+
+fn print_redirects(redirects: &[Redirect]) {
+    let rows = redirects
+        .iter()
+        .map(|r| {
+            vec![
+                r.key.clone(),
+                r.url.clone(),
+                r.visits.to_string(),
+                r.created_utc.clone(),
+            ]
+        })
+        .collect();
+    print_table(&["KEY", "URL", "VISITS", "CREATED"], rows);
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(headers.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+fn open_in_browser(url: &str) -> Result<(), CliError> {
+    let mut command = match std::env::consts::OS {
+        "macos" => std::process::Command::new("open"),
+        "windows" => {
+            let mut command = std::process::Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        _ => std::process::Command::new("xdg-open"),
+    };
+
+    let status = command
+        .arg(url)
+        .status()
+        .map_err(|err| CliError::Other(format!("Failed to open a browser: {}", err)))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(CliError::Other(format!(
+            "Failed to open a browser for {}",
+            url
+        ))),
+    }
+}
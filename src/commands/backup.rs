@@ -0,0 +1,68 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::Context;
+use futures_util::StreamExt;
+
+use crate::models::backup::{self, BackupFormat};
+
+#[derive(clap::Args)]
+pub struct ExportArgs {
+    /// jsonl or csv
+    #[arg(long, default_value = "jsonl")]
+    format: BackupFormat,
+    /// File to write the export to, stdout when left out
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct RestoreArgs {
+    /// Export to restore, `-` reads from stdin
+    file: PathBuf,
+    /// jsonl or csv, guessed from the file extension when left out
+    #[arg(long)]
+    format: Option<BackupFormat>,
+}
+
+pub async fn export(args: ExportArgs) -> anyhow::Result<()> {
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    let mut stream = Box::pin(backup::export_stream(args.format).await);
+    while let Some(chunk) = stream.next().await {
+        output
+            .write_all(chunk?.as_bytes())
+            .context("Failed to write export")?;
+    }
+    output.flush().context("Failed to write export")?;
+
+    Ok(())
+}
+
+pub async fn restore(args: RestoreArgs) -> anyhow::Result<()> {
+    let input = match args.file.as_os_str() == "-" {
+        true => std::io::read_to_string(std::io::stdin()).context("Failed to read stdin")?,
+        false => std::fs::read_to_string(&args.file)
+            .with_context(|| format!("Failed to read {}", args.file.display()))?,
+    };
+
+    let format = match args.format {
+        Some(format) => format,
+        None => match args.file.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => BackupFormat::Csv,
+            _ => BackupFormat::Jsonl,
+        },
+    };
+
+    let report = backup::restore(&input, format).await?;
+    for (table, count) in report.tables {
+        println!("{:>18}: {} rows", table, count);
+    }
+
+    Ok(())
+}
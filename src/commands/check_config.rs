@@ -0,0 +1,90 @@
+use crate::config::{Config, RateLimit};
+
+/// Print a summary of the loaded configuration
+/// Loading it already validated everything, so a broken deploy fails before getting here
+pub fn run(config: &Config) -> anyhow::Result<()> {
+    println!("Configuration is valid");
+    println!("  port:            {}", config.port);
+    println!(
+        "  base urls:       {}",
+        match config.base_urls.is_empty() {
+            true => "taken from each request".to_string(),
+            false => config
+                .base_urls
+                .iter()
+                .map(|base_url| base_url.origin.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    );
+    println!("  database:        {}", config.database.url);
+    println!("  local replica:   {}", config.database.local_path);
+    println!(
+        "  replica sync:    every {:?}, not ready after {:?} without one",
+        config.database.sync_interval, config.database.max_sync_age
+    );
+    if let Some(discord) = &config.discord {
+        println!(
+            "  discord login:   {} allowed guilds, {} with allowed roles, roles {}",
+            match discord.guilds.len() {
+                0 => "any".to_string(),
+                n => n.to_string(),
+            },
+            discord.guild_roles.len(),
+            match discord.role_map.is_empty() {
+                true => "managed with the CLI",
+                false => "mapped from Discord",
+            }
+        );
+    }
+    if let Some(github) = &config.github {
+        println!(
+            "  github login:    {}",
+            match (github.orgs.len(), github.teams.len()) {
+                (0, 0) => "any user".to_string(),
+                (orgs, teams) => format!("{} allowed orgs, {} allowed teams", orgs, teams),
+            }
+        );
+    }
+    if let Some(oidc) = &config.oidc {
+        println!("  oidc login:      {}", oidc.issuer);
+    }
+    println!(
+        "  geoip database:  {}",
+        config.geoip_db_path.as_deref().unwrap_or("disabled")
+    );
+    println!("  trusted proxies: {}", config.trusted_proxies.len());
+    println!("  shutdown:        {:?}", config.shutdown_timeout);
+    println!(
+        "  token lifetimes: access {:?}, refresh {:?}",
+        config.session.access_token_ttl, config.session.refresh_token_ttl
+    );
+    println!("  log format:      {:?}", config.log_format);
+    println!(
+        "  otlp export:     {}",
+        config.otlp_endpoint.as_deref().unwrap_or("disabled")
+    );
+    let rate_limit = |limit: Option<RateLimit>| match limit {
+        Some(limit) => format!("{}/{:?}", limit.requests, limit.period),
+        None => "off".to_string(),
+    };
+    println!(
+        "  rate limits:     redirect {}, api {}, auth {}",
+        rate_limit(config.rate_limit.redirect),
+        rate_limit(config.rate_limit.api),
+        rate_limit(config.rate_limit.auth)
+    );
+    println!(
+        "  metrics:         {}{}",
+        match config.metrics.port {
+            Some(port) => format!("port {}", port),
+            None => "app port".to_string(),
+        },
+        match config.metrics.token {
+            Some(_) => ", token required",
+            None => "",
+        }
+    );
+
+    Ok(())
+}
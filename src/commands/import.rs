@@ -0,0 +1,71 @@
+use std::{io::Read, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::{
+    models::{self, redirect::ConflictPolicy},
+    utils::import::{self, ImportFormat},
+};
+
+#[derive(clap::Args)]
+pub struct ImportArgs {
+    /// File to import, `-` reads from stdin
+    file: PathBuf,
+    /// csv, json, yourls or bitly, guessed from the file extension when left out
+    #[arg(long)]
+    format: Option<ImportFormat>,
+    /// What to do with keys that already exist: skip, overwrite or rename
+    #[arg(long, default_value = "skip")]
+    on_conflict: ConflictPolicy,
+    /// Validate the file and report what would happen without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Host for rows that don't bring their own
+    #[arg(long)]
+    host: String,
+    /// Discord snowflake, or `<provider>:<subject>`, of the user the imported links will belong to
+    #[arg(long)]
+    owner: String,
+}
+
+pub async fn run(args: ImportArgs) -> anyhow::Result<()> {
+    let mut input = String::new();
+    if args.file.as_os_str() == "-" {
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read stdin")?;
+    } else {
+        input = std::fs::read_to_string(&args.file)
+            .with_context(|| format!("Failed to read {}", args.file.display()))?;
+    }
+
+    let format = match args.format {
+        Some(format) => format,
+        None => match args.file.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => ImportFormat::Json,
+            _ => ImportFormat::Csv,
+        },
+    };
+
+    let owner = models::user::find_user(&args.owner)
+        .await
+        .context("Owner has to be a user who has logged in before")?;
+
+    let rows = import::parse(&input, format)?;
+    let report = models::redirect::import_redirects(
+        rows,
+        &args.host,
+        owner.id,
+        args.on_conflict,
+        args.dry_run,
+    )
+    .await?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if report.failed > 0 {
+        anyhow::bail!("{} rows failed, nothing was imported", report.failed);
+    }
+
+    Ok(())
+}
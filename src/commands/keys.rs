@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+
+/// DER prefix of a SubjectPublicKeyInfo holding an Ed25519 key, the 32 key bytes follow it
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+#[derive(clap::Subcommand)]
+pub enum KeysCommand {
+    /// Generate the Ed25519 key pair used to sign and verify login tokens
+    Generate(GenerateArgs),
+}
+
+#[derive(clap::Args)]
+pub struct GenerateArgs {
+    /// Write `jwt_private.pem` and `jwt_public.pem` into this directory instead of
+    /// printing `JWT_SECRET_PRIVATE` and `JWT_SECRET_PUBLIC` lines for a .env file
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+pub fn run(command: KeysCommand) -> anyhow::Result<()> {
+    match command {
+        KeysCommand::Generate(args) => generate(args),
+    }
+}
+
+fn generate(args: GenerateArgs) -> anyhow::Result<()> {
+    let (private_pem, public_pem) = generate_pem_pair()?;
+
+    match args.out {
+        Some(dir) => {
+            let private_path = dir.join("jwt_private.pem");
+            let public_path = dir.join("jwt_public.pem");
+            write_private(&private_path, &private_pem)?;
+            std::fs::write(&public_path, &public_pem)
+                .with_context(|| format!("Failed to write {}", public_path.display()))?;
+            println!(
+                "Wrote {} and {}",
+                private_path.display(),
+                public_path.display()
+            );
+        }
+        None => {
+            println!("JWT_SECRET_PRIVATE=\"{}\"", private_pem.trim_end());
+            println!("JWT_SECRET_PUBLIC=\"{}\"", public_pem.trim_end());
+        }
+    }
+
+    Ok(())
+}
+
+/// A PKCS#8 private key and the matching SPKI public key, both PEM encoded
+pub fn generate_pem_pair() -> anyhow::Result<(String, String)> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow::anyhow!("Failed to generate Ed25519 key"))?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| anyhow::anyhow!("Failed to read generated Ed25519 key"))?;
+
+    let mut spki = ED25519_SPKI_PREFIX.to_vec();
+    spki.extend_from_slice(key_pair.public_key().as_ref());
+
+    Ok((
+        to_pem("PRIVATE KEY", pkcs8.as_ref()),
+        to_pem("PUBLIC KEY", &spki),
+    ))
+}
+
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+#[cfg(unix)]
+fn write_private(path: &PathBuf, contents: &str) -> anyhow::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &PathBuf, contents: &str) -> anyhow::Result<()> {
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+
+use crate::utils::strings::generate_random_string;
+
+const KEY_ID: &str = "mock";
+const ID_TOKEN_LIFETIME_SECS: i64 = 300;
+
+#[derive(clap::Args)]
+pub struct MockOidcArgs {
+    /// Port to listen on, the issuer is `http://localhost:<port>`
+    #[arg(long, default_value_t = 9000)]
+    port: u16,
+    /// `sub` of the account everyone logs in as
+    #[arg(long, default_value = "mock-user")]
+    subject: String,
+    /// `preferred_username` of that account
+    #[arg(long, default_value = "mock")]
+    username: String,
+}
+
+/// An authorization code handed out and not yet traded for tokens
+struct PendingCode {
+    client_id: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    challenge: Option<String>,
+}
+
+struct MockIssuer {
+    issuer: String,
+    subject: String,
+    username: String,
+    encoding: EncodingKey,
+    /// base64url of the Ed25519 public key, the `x` of its JWK
+    public_key: String,
+    codes: Mutex<HashMap<String, PendingCode>>,
+}
+
+/// Serve an OpenID Connect issuer that logs everyone in as the same account without asking,
+/// for trying the OIDC login with `OIDC_ISSUER=http://localhost:<port>` and any `OIDC_CLIENT_ID`
+/// Keys live in memory only, restarting it invalidates nothing shidou keeps
+pub async fn run(args: MockOidcArgs) -> anyhow::Result<()> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow::anyhow!("Failed to generate Ed25519 key"))?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| anyhow::anyhow!("Failed to read generated Ed25519 key"))?;
+
+    let issuer = format!("http://localhost:{}", args.port);
+    let state = Arc::new(MockIssuer {
+        issuer: issuer.clone(),
+        subject: args.subject,
+        username: args.username,
+        encoding: EncodingKey::from_ed_der(pkcs8.as_ref()),
+        public_key: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+        codes: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .with_state(state);
+
+    let listener = TcpListener::bind(("127.0.0.1", args.port))
+        .await
+        .context("error while binding to port")?;
+    tracing::info!("Mock OIDC issuer running at {}", issuer);
+    axum::serve(listener, app)
+        .await
+        .context("error while serving mock issuer")
+}
+
+async fn discovery(State(state): State<Arc<MockIssuer>>) -> impl IntoResponse {
+    Json(json!({
+        "issuer": state.issuer,
+        "authorization_endpoint": format!("{}/authorize", state.issuer),
+        "token_endpoint": format!("{}/token", state.issuer),
+        "jwks_uri": format!("{}/jwks", state.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["EdDSA"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn jwks(State(state): State<Arc<MockIssuer>>) -> impl IntoResponse {
+    Json(json!({
+        "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": state.public_key,
+            "kid": KEY_ID,
+            "alg": "EdDSA",
+            "use": "sig",
+        }]
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+}
+
+/// Skip the login form and send the browser straight back with a code
+async fn authorize(
+    State(state): State<Arc<MockIssuer>>,
+    Query(query): Query<AuthorizeQuery>,
+) -> Response {
+    let mut redirect = match reqwest::Url::parse(&query.redirect_uri) {
+        Ok(url) => url,
+        Err(_) => return oauth_error("invalid_request", "redirect_uri is not a url"),
+    };
+
+    let code = generate_random_string(32);
+    redirect.query_pairs_mut().append_pair("code", &code);
+    if let Some(oauth_state) = &query.state {
+        redirect.query_pairs_mut().append_pair("state", oauth_state);
+    }
+
+    state.codes.lock().unwrap().insert(
+        code,
+        PendingCode {
+            client_id: query.client_id,
+            redirect_uri: query.redirect_uri,
+            nonce: query.nonce,
+            challenge: query.code_challenge,
+        },
+    );
+
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+#[derive(serde::Deserialize)]
+struct TokenForm {
+    code: String,
+    client_id: String,
+    redirect_uri: String,
+    code_verifier: Option<String>,
+}
+
+async fn token(State(state): State<Arc<MockIssuer>>, Form(form): Form<TokenForm>) -> Response {
+    let pending = match state.codes.lock().unwrap().remove(&form.code) {
+        Some(pending) => pending,
+        None => return oauth_error("invalid_grant", "unknown or used code"),
+    };
+    if pending.client_id != form.client_id || pending.redirect_uri != form.redirect_uri {
+        return oauth_error("invalid_grant", "client_id or redirect_uri don't match");
+    }
+    if let Some(challenge) = pending.challenge {
+        let verifier = form.code_verifier.unwrap_or_default();
+        if URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) != challenge {
+            return oauth_error("invalid_grant", "code_verifier doesn't match");
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = json!({
+        "iss": state.issuer,
+        "sub": state.subject,
+        "aud": form.client_id,
+        "iat": now,
+        "exp": now + ID_TOKEN_LIFETIME_SECS,
+        "nonce": pending.nonce,
+        "preferred_username": state.username,
+    });
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = match encode(&header, &claims, &state.encoding) {
+        Ok(id_token) => id_token,
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    Json(json!({
+        "access_token": generate_random_string(32),
+        "token_type": "Bearer",
+        "expires_in": ID_TOKEN_LIFETIME_SECS,
+        "id_token": id_token,
+    }))
+    .into_response()
+}
+
+fn oauth_error(error: &str, description: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": error, "error_description": description })),
+    )
+        .into_response()
+}
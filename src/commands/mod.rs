@@ -0,0 +1,7 @@
+pub mod backup;
+pub mod check_config;
+pub mod import;
+pub mod keys;
+pub mod mock_oidc;
+pub mod redirect;
+pub mod user;
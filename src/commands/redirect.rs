@@ -0,0 +1,70 @@
+use anyhow::Context;
+
+use crate::{
+    models::{
+        self,
+        redirect::{self, OpenGraph},
+    },
+    utils::strings,
+};
+
+#[derive(clap::Subcommand)]
+pub enum RedirectCommand {
+    /// Create a redirect
+    Add {
+        key: String,
+        url: String,
+        /// Host the short link is served on, e.g. `shidou.example.com`
+        #[arg(long)]
+        host: String,
+        /// Discord snowflake, or `<provider>:<subject>`, of the user the redirect will belong to
+        #[arg(long)]
+        owner: String,
+        /// Show a preview page before sending visitors to another site
+        #[arg(long)]
+        interstitial: bool,
+    },
+    /// Delete a redirect along with its rules, locales, variants and visits
+    Rm { key: String },
+}
+
+pub async fn run(command: RedirectCommand) -> anyhow::Result<()> {
+    match command {
+        RedirectCommand::Add {
+            key,
+            url,
+            host,
+            owner,
+            interstitial,
+        } => {
+            strings::validate_key(&key).map_err(anyhow::Error::msg)?;
+            if redirect::get_redirect_by_key(&key).await?.is_some() {
+                anyhow::bail!("Key {} is already taken", key);
+            }
+            let owner = models::user::find_user(&owner)
+                .await
+                .context("Owner has to be a user who has logged in before")?;
+
+            let url = strings::normalize_url(&url);
+            let redirect = redirect::save_new_redirect(
+                &key,
+                &url,
+                &host,
+                owner.id,
+                interstitial,
+                &OpenGraph::default(),
+            )
+            .await?;
+            println!("{} -> {}", redirect.key, redirect.url);
+        }
+        RedirectCommand::Rm { key } => {
+            let redirect = redirect::get_redirect_by_key(&key)
+                .await?
+                .with_context(|| format!("No redirect with key {}", key))?;
+            redirect::delete_redirect(&redirect.key).await?;
+            println!("Deleted {}", key);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::{
+    config::Config,
+    models::{
+        session,
+        user::{self, ROLE_ADMIN, ROLE_MEMBER},
+    },
+};
+
+#[derive(clap::Subcommand)]
+pub enum UserCommand {
+    /// Make a user an admin, they have to have logged in once
+    ///
+    /// With a Discord role map configured, Discord users get their mapped role back at their next login
+    Promote {
+        /// Discord snowflake of the user, or `<provider>:<subject>` for other logins
+        login: String,
+    },
+    /// Take admin rights away from a user
+    ///
+    /// With a Discord role map configured, Discord users get their mapped role back at their next login
+    Demote {
+        /// Discord snowflake of the user, or `<provider>:<subject>` for other logins
+        login: String,
+    },
+    /// Lock a user out, their sessions and API tokens stop working
+    Disable {
+        /// Discord snowflake of the user, or `<provider>:<subject>` for other logins
+        login: String,
+    },
+    /// Let a disabled user back in
+    Enable {
+        /// Discord snowflake of the user, or `<provider>:<subject>` for other logins
+        login: String,
+    },
+    /// Log a user out of every browser, API tokens keep working
+    RevokeSessions {
+        /// Discord snowflake of the user, or `<provider>:<subject>` for other logins
+        login: String,
+    },
+}
+
+pub async fn run(command: UserCommand, config: &Config) -> anyhow::Result<()> {
+    let (login, role) = match &command {
+        UserCommand::Promote { login } => (login, ROLE_ADMIN),
+        UserCommand::Demote { login } => (login, ROLE_MEMBER),
+        UserCommand::RevokeSessions { login } => {
+            let user = user::find_user(login).await?;
+            let revoked = session::delete_user_sessions(user.id).await?;
+            println!("Revoked {} sessions of {}", revoked, user.discord_username);
+            return Ok(());
+        }
+        UserCommand::Disable { login } | UserCommand::Enable { login } => {
+            let disabled = matches!(command, UserCommand::Disable { .. });
+            let user = user::find_user(login).await?;
+            let user = user::set_user_disabled(user.id, disabled).await?;
+            println!(
+                "{} is now {}",
+                user.discord_username,
+                match user.disabled {
+                    true => "disabled",
+                    false => "enabled",
+                }
+            );
+            return Ok(());
+        }
+    };
+
+    let user = user::find_user(login).await?;
+    let user = user::set_user_role(user.id, role).await?;
+    println!("{} is now {}", user.discord_username, user.role);
+    if config
+        .discord
+        .as_ref()
+        .is_some_and(|discord| !discord.role_map.is_empty())
+    {
+        tracing::warn!(
+            "Roles are mapped from Discord, a Discord login of {} resets the role to the mapped one",
+            user.discord_username
+        );
+    }
+
+    Ok(())
+}
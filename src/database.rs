@@ -1,43 +1,124 @@
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use libsql::Builder;
-use std::env;
-use tokio::sync::OnceCell;
+use tokio::{sync::OnceCell, time::MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{config::DatabaseConfig, models::identity};
 
 static DATABASE: OnceCell<libsql::Database> = OnceCell::const_new();
+/// When the local replica last caught up with the remote database
+static LAST_SYNC: Mutex<Option<DateTime<Utc>>> = Mutex::new(None);
 
-async fn get_db() -> &'static libsql::Database {
-    DATABASE
-        .get_or_init(|| async {
-            let db = Builder::new_remote_replica(
-                env::var("LIBSQL_LOCAL_DB_PATH").unwrap_or("file:local_replica.db".to_string()),
-                env::var("LIBSQL_CLIENT_URL").expect("Missing LIBSQL_CLIENT_URL"),
-                env::var("LIBSQL_CLIENT_TOKEN").expect("Missing LIBSQL_CLIENT_TOKEN"),
-            )
-            .build()
-            .await
-            .expect("Failed to create database");
+/// Open the local replica and sync it with the remote database, has to run before `get_conn`
+pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<()> {
+    let db = Builder::new_remote_replica(
+        config.local_path.clone(),
+        config.url.clone(),
+        config.token.clone(),
+    )
+    .build()
+    .await
+    .context("Failed to create database")?;
 
-            db.sync()
-                .await
-                .expect("Failed to sync remote db to local disk");
+    db.sync()
+        .await
+        .context("Failed to sync remote db to local disk")?;
+    record_sync();
 
-            tracing::debug!("Initialized db");
+    tracing::debug!("Initialized db");
 
-            db
-        })
-        .await
+    DATABASE
+        .set(db)
+        .map_err(|_| anyhow::anyhow!("Database is already connected"))
+}
+
+async fn get_db() -> &'static libsql::Database {
+    DATABASE
+        .get()
+        .expect("database::connect has to be called before using the database")
 }
 
 pub async fn get_conn() -> libsql::Connection {
     get_db().await.connect().expect("Failed to connect to db")
 }
 
-// async fn sync() -> anyhow::Result<()> {
-//     let db = get_db().await;
-//     db.sync().await?;
-//     tracing::trace!("Synced remote db to local disk");
-//     Ok(())
-// }
+pub async fn sync() -> anyhow::Result<()> {
+    let db = get_db().await;
+    db.sync()
+        .await
+        .context("Failed to sync remote db to local disk")?;
+    record_sync();
+    tracing::trace!("Synced remote db to local disk");
+    Ok(())
+}
+
+/// Sync the replica every `interval` until `shutdown`, a sync that fails or hangs leaves `last_sync` behind
+/// for `/readyz` to notice, the local file alone would keep answering queries as if nothing happened
+pub async fn sync_periodically(interval: Duration, shutdown: CancellationToken) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick is immediate, `connect` just synced
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+        match tokio::time::timeout(interval, sync()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::warn!("Database sync failed: {:?}", err),
+            Err(_) => tracing::warn!("Database sync took longer than {:?}", interval),
+        }
+    }
+}
+
+fn record_sync() {
+    if let Ok(mut last_sync) = LAST_SYNC.lock() {
+        *last_sync = Some(Utc::now());
+    }
+}
+
+pub fn last_sync() -> Option<DateTime<Utc>> {
+    LAST_SYNC.lock().ok().and_then(|last_sync| *last_sync)
+}
+
+/// Await a query inside a span and record its latency as `shidou_db_query_duration_seconds`
+pub async fn timed<T>(query: &'static str, future: impl Future<Output = T>) -> T {
+    let span = tracing::info_span!(
+        "db.query",
+        otel.name = query,
+        otel.kind = "client",
+        db.system = "sqlite",
+        db.operation = query,
+    );
+    let started = Instant::now();
+    let result = future.instrument(span).await;
+    metrics::histogram!("shidou_db_query_duration_seconds", "query" => query)
+        .record(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Run a trivial query to make sure the database answers
+/// The local replica answers it, whether the remote still does shows in `last_sync`
+pub async fn ping() -> anyhow::Result<()> {
+    timed("ping", async {
+        let conn = get_conn().await;
+        conn.query("SELECT 1", libsql::params!())
+            .await
+            .context("Failed to query database")
+    })
+    .await?;
+    Ok(())
+}
 
 pub async fn init_tables() -> anyhow::Result<()> {
     let conn = get_conn().await;
@@ -77,6 +158,94 @@ pub async fn init_tables() -> anyhow::Result<()> {
     .await
     .context("Failed to create index on redirects table")?;
 
+    //
+    // Redirect rules table
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS redirect_rules (
+                id INTEGER PRIMARY KEY,
+                redirect_id INTEGER,
+                priority INTEGER DEFAULT 0,
+                os TEXT,
+                device TEXT,
+                bot INTEGER,
+                url TEXT,
+                FOREIGN KEY(redirect_id) REFERENCES redirects(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create redirect_rules table")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_redirect_rules_redirect_id ON redirect_rules(redirect_id)",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create index on redirect_rules table")?;
+
+    //
+    // Redirect locales table
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS redirect_locales (
+                id INTEGER PRIMARY KEY,
+                redirect_id INTEGER,
+                language TEXT,
+                url TEXT,
+                UNIQUE(redirect_id, language),
+                FOREIGN KEY(redirect_id) REFERENCES redirects(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create redirect_locales table")?;
+
+    //
+    // Redirect targets table, the variants of a weighted split
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS redirect_targets (
+                id INTEGER PRIMARY KEY,
+                redirect_id INTEGER,
+                url TEXT,
+                weight INTEGER DEFAULT 1,
+                clicks INTEGER DEFAULT 0,
+                created_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                FOREIGN KEY(redirect_id) REFERENCES redirects(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create redirect_targets table")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_redirect_targets_redirect_id ON redirect_targets(redirect_id)",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create index on redirect_targets table")?;
+
+    //
+    // Visits table
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS visits (
+                id INTEGER PRIMARY KEY,
+                redirect_id INTEGER,
+                locale TEXT,
+                visited_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                FOREIGN KEY(redirect_id) REFERENCES redirects(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create visits table")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_visits_redirect_id ON visits(redirect_id)",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create index on visits table")?;
+
     //
     // Users table
     //
@@ -99,7 +268,122 @@ pub async fn init_tables() -> anyhow::Result<()> {
     .await
     .context("Failed to create index on users table")?;
 
+    //
+    // API tokens table, only the SHA-256 of a token is stored
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                token_hash TEXT UNIQUE NOT NULL,
+                created_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                last_used_utc REAL,
+                FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create api_tokens table")?;
+
+    //
+    // Sessions table, every login token names its session in the `sid` claim
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY,
+                sid TEXT UNIQUE NOT NULL,
+                user_id INTEGER NOT NULL,
+                user_agent TEXT,
+                ip TEXT,
+                created_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                last_seen_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                expires_utc REAL NOT NULL,
+                FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create sessions table")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id)",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create index on sessions table")?;
+
+    //
+    // Identities table, the accounts at login providers each user logs in with
+    //
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS identities (
+                id INTEGER PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                provider TEXT NOT NULL,
+                subject TEXT NOT NULL,
+                username TEXT,
+                created_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                last_login_utc REAL DEFAULT (strftime('%Y-%m-%d %H:%M:%S', 'now')),
+                UNIQUE(provider, subject),
+                FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+            )",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create identities table")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_identities_user_id ON identities(user_id)",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to create index on identities table")?;
+    identity::link_discord_users(&tx).await?;
+
+    //
+    // Columns added after the initial schema
+    //
+    add_column(&tx, "redirects", "interstitial", "INTEGER DEFAULT 0").await?;
+    add_column(&tx, "redirects", "og_title", "TEXT").await?;
+    add_column(&tx, "redirects", "og_description", "TEXT").await?;
+    add_column(&tx, "redirects", "og_image", "TEXT").await?;
+    add_column(&tx, "visits", "target_id", "INTEGER").await?;
+    add_column(&tx, "visits", "country", "TEXT").await?;
+    add_column(&tx, "redirect_rules", "country", "TEXT").await?;
+    add_column(&tx, "users", "role", "TEXT DEFAULT 'member'").await?;
+    add_column(&tx, "users", "disabled", "INTEGER DEFAULT 0").await?;
+    add_column(&tx, "sessions", "refresh_hash", "TEXT").await?;
+    add_column(&tx, "sessions", "previous_refresh_hash", "TEXT").await?;
+    add_column(&tx, "sessions", "rotated_utc", "REAL").await?;
+
     tx.commit().await.context("Failed to commit transaction")?;
 
     Ok(())
 }
+
+/// Add a column to an existing table, skipping it when an earlier startup already added it
+async fn add_column(
+    tx: &libsql::Transaction,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> anyhow::Result<()> {
+    let mut rows = tx
+        .query(&format!("PRAGMA table_info({})", table), libsql::params!())
+        .await
+        .with_context(|| format!("Failed to read columns of {} table", table))?;
+
+    while let Ok(Some(row)) = rows.next().await {
+        if row.get::<String>(1)? == column {
+            return Ok(());
+        }
+    }
+
+    tx.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+        libsql::params!(),
+    )
+    .await
+    .with_context(|| format!("Failed to add {} column to {} table", column, table))?;
+
+    Ok(())
+}
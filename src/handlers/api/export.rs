@@ -0,0 +1,46 @@
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        HeaderValue,
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use futures_util::StreamExt;
+use serde::Deserialize;
+
+use crate::models::backup::{self, BackupFormat};
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: BackupFormat,
+}
+
+/// Stream a full export of the database, e.g. `GET /api/export?format=csv`
+pub async fn get(Query(query): Query<ExportQuery>) -> impl IntoResponse {
+    let stream = backup::export_stream(query.format).await.map(|chunk| {
+        chunk.map_err(|err| {
+            tracing::error!("Failed to export database: {:?}", err);
+            std::io::Error::other(err.to_string())
+        })
+    });
+
+    let filename = format!(
+        "attachment; filename=\"shidou-export-{}.{}\"",
+        Utc::now().format("%Y-%m-%d"),
+        query.format.extension()
+    );
+
+    let mut response = Response::new(Body::from_stream(stream));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(query.format.content_type()),
+    );
+    if let Ok(value) = HeaderValue::from_str(&filename) {
+        response.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+    response
+}
@@ -0,0 +1,66 @@
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    Extension,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    middleware::{auth::Principal, public_url::PublicUrl},
+    models::redirect::{self, ConflictPolicy},
+    utils::import::{self, ImportFormat},
+};
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    format: ImportFormat,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+    #[serde(default)]
+    dry_run: bool,
+    /// Host for rows that don't bring their own, defaults to the host of this request
+    host: Option<String>,
+}
+
+/// Import redirects from the export in the request body
+/// e.g. `POST /api/import?format=yourls&on_conflict=rename&dry_run=true`
+pub async fn post(
+    Extension(public_url): Extension<PublicUrl>,
+    principal: Principal,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> impl IntoResponse {
+    let rows = match import::parse(&body, query.format) {
+        Ok(rows) => rows,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": format!("{:#}", err) })),
+            )
+                .into_response()
+        }
+    };
+    let host = query.host.unwrap_or(public_url.host);
+
+    match redirect::import_redirects(
+        rows,
+        &host,
+        principal.user_id,
+        query.on_conflict,
+        query.dry_run,
+    )
+    .await
+    {
+        Ok(report) if report.failed > 0 => {
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(report)).into_response()
+        }
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
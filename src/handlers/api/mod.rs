@@ -1 +1,5 @@
+pub mod export;
+pub mod import;
 pub mod redirect;
+pub mod session;
+pub mod token;
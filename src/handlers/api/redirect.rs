@@ -1,24 +1,22 @@
+use std::collections::{BTreeMap, HashMap};
+
 use axum::{
-    http::{HeaderMap, StatusCode},
+    extract::{Path, Query},
+    http::StatusCode,
     response::{IntoResponse, Json},
     Extension,
 };
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use serde_json::json;
+use shidou::types::{DeleteRedirectInput, RedirectInput};
 use tracing::trace;
 
-use crate::{middleware::auth::UserId, models::redirect, utils::strings};
-
-#[derive(Serialize, Deserialize)]
-pub struct RedirectInput {
-    key: String,
-    url: String,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct DeleteRedirectInput {
-    key: String,
-}
+use crate::{
+    handlers,
+    middleware::{auth::Principal, public_url::PublicUrl},
+    models::{locale, redirect, rule, target, visit},
+    utils::strings,
+};
 
 pub async fn get() -> impl IntoResponse {
     match redirect::get_all_redirects().await {
@@ -31,18 +29,47 @@ pub async fn get() -> impl IntoResponse {
     }
 }
 
+pub async fn get_one(Path(key): Path<String>) -> impl IntoResponse {
+    match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => Json(redirect).into_response(),
+        Ok(None) => redirect_not_found(),
+        Err(err) => internal_error(err),
+    }
+}
+
 pub async fn post(
-    headers: HeaderMap,
-    Extension(user_id): Extension<UserId>,
+    Extension(public_url): Extension<PublicUrl>,
+    principal: Principal,
     Json(payload): Json<RedirectInput>,
 ) -> impl IntoResponse {
-    let url = match &payload.url {
-        url if url.starts_with("http://") || url.starts_with("https://") => url.to_owned(),
-        _ => format!("http://{}", payload.url),
-    };
-    let host = strings::get_host_header(&headers, false);
+    if let Err(err) = strings::validate_key(&payload.key) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": err }))).into_response();
+    }
+    let url = strings::normalize_url(&payload.url);
+    let host = public_url.host;
 
-    match redirect::save_new_redirect(&payload.key, &url, &host, user_id.into_i64()).await {
+    match redirect::get_redirect_by_key(&payload.key).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({ "error": format!("Key {} is already taken", payload.key) })),
+            )
+                .into_response()
+        }
+        Err(err) => return internal_error(err),
+    }
+
+    match redirect::save_new_redirect(
+        &payload.key,
+        &url,
+        &host,
+        principal.user_id,
+        payload.interstitial.unwrap_or(false),
+        &payload.og,
+    )
+    .await
+    {
         Ok(redirect) => (StatusCode::OK, Json(redirect)).into_response(),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -52,14 +79,16 @@ pub async fn post(
     }
 }
 
-pub async fn put(headers: HeaderMap, Json(payload): Json<RedirectInput>) -> impl IntoResponse {
-    let url = match &payload.url {
-        url if url.starts_with("http://") || url.starts_with("https://") => url.to_owned(),
-        _ => format!("http://{}", payload.url),
-    };
-    let host = strings::get_host_header(&headers, false);
+pub async fn put(Json(payload): Json<RedirectInput>) -> impl IntoResponse {
+    let url = strings::normalize_url(&payload.url);
+
+    match redirect::get_redirect_by_key(&payload.key).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    }
 
-    match redirect::update_redirect(&payload.key, &url, &host).await {
+    match redirect::update_redirect(&payload.key, &url, payload.interstitial, &payload.og).await {
         Ok(redirect) => (StatusCode::OK, Json(redirect)).into_response(),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -69,12 +98,14 @@ pub async fn put(headers: HeaderMap, Json(payload): Json<RedirectInput>) -> impl
     }
 }
 
-pub async fn delete(
-    headers: HeaderMap,
-    Json(payload): Json<DeleteRedirectInput>,
-) -> impl IntoResponse {
-    let host = strings::get_host_header(&headers, false);
-    match redirect::delete_redirect(&payload.key, &host).await {
+pub async fn delete(Json(payload): Json<DeleteRedirectInput>) -> impl IntoResponse {
+    match redirect::get_redirect_by_key(&payload.key).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    }
+
+    match redirect::delete_redirect(&payload.key).await {
         Ok(_) => (
             StatusCode::OK,
             Json(json!({ "message": "Redirect deleted successfully" })),
@@ -87,3 +118,208 @@ pub async fn delete(
             .into_response(),
     }
 }
+
+pub async fn get_qr(
+    Extension(public_url): Extension<PublicUrl>,
+    Path(key): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    handlers::qr::qr_response(&key, &public_url, &params).await
+}
+
+pub async fn get_rules(Path(key): Path<String>) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    match rule::get_rules(redirect.id).await {
+        Ok(rules) => Json(rules).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+pub async fn put_rules(
+    Path(key): Path<String>,
+    Json(mut rules): Json<Vec<rule::RuleInput>>,
+) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    for rule in rules.iter_mut() {
+        rule.url = strings::normalize_url(&rule.url);
+    }
+
+    match rule::replace_rules(redirect.id, &rules).await {
+        Ok(rules) => Json(rules).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+pub async fn get_locales(Path(key): Path<String>) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    match locale::get_locales(redirect.id).await {
+        Ok(locales) => Json(locales_to_map(locales)).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Replace the language map of a redirect, e.g. `{ "en": "https://example.com/en/" }`
+pub async fn put_locales(
+    Path(key): Path<String>,
+    Json(locales): Json<BTreeMap<String, String>>,
+) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    let locales = locales
+        .into_iter()
+        .map(|(language, url)| (language, strings::normalize_url(&url)))
+        .collect();
+
+    match locale::replace_locales(redirect.id, &locales).await {
+        Ok(locales) => Json(locales_to_map(locales)).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+fn locales_to_map(locales: Vec<locale::LocaleRow>) -> BTreeMap<String, String> {
+    locales.into_iter().map(|l| (l.language, l.url)).collect()
+}
+
+#[derive(Serialize)]
+pub struct TargetResult {
+    #[serde(flatten)]
+    target: target::TargetRow,
+    /// Share of all clicks on the split that went to this variant
+    click_share: f64,
+}
+
+/// List the split variants of a redirect along with their results
+pub async fn get_targets(Path(key): Path<String>) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    let targets = match target::get_targets(redirect.id).await {
+        Ok(targets) => targets,
+        Err(err) => return internal_error(err),
+    };
+
+    let total_clicks: u64 = targets.iter().map(|t| t.clicks).sum();
+    let results: Vec<TargetResult> = targets
+        .into_iter()
+        .map(|target| TargetResult {
+            click_share: match total_clicks {
+                0 => 0.0,
+                total => target.clicks as f64 / total as f64,
+            },
+            target,
+        })
+        .collect();
+
+    Json(results).into_response()
+}
+
+pub async fn post_target(
+    Path(key): Path<String>,
+    Json(mut payload): Json<target::TargetInput>,
+) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    payload.url = strings::normalize_url(&payload.url);
+    if let Err(err) = target::add_target(redirect.id, &payload).await {
+        return internal_error(err);
+    }
+
+    match target::get_targets(redirect.id).await {
+        Ok(targets) => Json(targets).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+pub async fn put_target(
+    Path((key, target_id)): Path<(String, i64)>,
+    Json(mut payload): Json<target::TargetInput>,
+) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    payload.url = strings::normalize_url(&payload.url);
+    if let Err(err) = target::update_target(redirect.id, target_id, &payload).await {
+        return internal_error(err);
+    }
+
+    match target::get_targets(redirect.id).await {
+        Ok(targets) => Json(targets).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+pub async fn delete_target(Path((key, target_id)): Path<(String, i64)>) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    match target::delete_target(redirect.id, target_id).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(json!({ "message": "Target deleted successfully" })),
+        )
+            .into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Visits of a redirect per country, empty unless a GeoIP database is configured
+pub async fn get_countries(Path(key): Path<String>) -> impl IntoResponse {
+    let redirect = match redirect::get_redirect_by_key(&key).await {
+        Ok(Some(redirect)) => redirect,
+        Ok(None) => return redirect_not_found(),
+        Err(err) => return internal_error(err),
+    };
+
+    match visit::count_by_country(redirect.id).await {
+        Ok(counts) => Json(counts).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+fn redirect_not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Redirect not found" })),
+    )
+        .into_response()
+}
+
+fn internal_error(err: anyhow::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": err.to_string() })),
+    )
+        .into_response()
+}
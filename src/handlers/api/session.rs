@@ -0,0 +1,83 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use serde_json::json;
+
+use crate::{
+    middleware::auth::Principal,
+    models::{
+        self,
+        session::{self, SessionRow},
+    },
+};
+
+#[derive(serde::Serialize)]
+struct SessionResponse {
+    #[serde(flatten)]
+    session: SessionRow,
+    /// The session making this request
+    current: bool,
+}
+
+/// List the sessions of the current user, the browsers and devices they are logged in on
+pub async fn get(principal: Principal) -> impl IntoResponse {
+    let current = principal.session.clone();
+
+    match session::get_sessions(principal.user_id).await {
+        Ok(sessions) => Json(
+            sessions
+                .into_iter()
+                .map(|session| SessionResponse {
+                    current: current.as_deref() == Some(session.sid.as_str()),
+                    session,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Revoke one of the current user's sessions, its login token stops working right away
+pub async fn delete(principal: Principal, Path(id): Path<i64>) -> impl IntoResponse {
+    match session::delete_session(principal.user_id, id).await {
+        Ok(true) => Json(json!({ "message": "Session revoked" })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Session not found" })),
+        )
+            .into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Log a user out everywhere, admins only
+pub async fn delete_user_sessions(Path(login): Path<String>) -> impl IntoResponse {
+    let user = match models::user::find_user(&login).await {
+        Ok(user) => user,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "User not found" })),
+            )
+                .into_response()
+        }
+    };
+
+    match session::delete_user_sessions(user.id).await {
+        Ok(revoked) => {
+            Json(json!({ "message": "Sessions revoked", "revoked": revoked })).into_response()
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
+fn internal_error(err: anyhow::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": err.to_string() })),
+    )
+        .into_response()
+}
@@ -0,0 +1,74 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use serde_json::json;
+use shidou::types::{NewToken, TokenInput};
+
+use crate::{
+    middleware::auth::{Principal, TokenKind},
+    models::token,
+};
+
+/// List the API tokens of the current user, without the tokens themselves
+pub async fn get(principal: Principal) -> impl IntoResponse {
+    match token::get_tokens(principal.user_id).await {
+        Ok(tokens) => Json(tokens).into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Create an API token for `shidou-cli` and other scripts, e.g. `{ "name": "laptop" }`
+pub async fn post(principal: Principal, Json(payload): Json<TokenInput>) -> impl IntoResponse {
+    // a leaked API token must not be able to mint more of itself
+    if principal.kind != TokenKind::Session {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "API tokens can only be created from the web UI" })),
+        )
+            .into_response();
+    }
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Token name must not be empty" })),
+        )
+            .into_response();
+    }
+
+    match token::create_token(principal.user_id, name).await {
+        Ok((row, token)) => (
+            StatusCode::CREATED,
+            Json(NewToken {
+                id: row.id,
+                name: row.name,
+                token,
+            }),
+        )
+            .into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+pub async fn delete(principal: Principal, Path(id): Path<i64>) -> impl IntoResponse {
+    match token::delete_token(principal.user_id, id).await {
+        Ok(true) => Json(json!({ "message": "Token revoked" })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Token not found" })),
+        )
+            .into_response(),
+        Err(err) => internal_error(err),
+    }
+}
+
+fn internal_error(err: anyhow::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": err.to_string() })),
+    )
+        .into_response()
+}
@@ -1,58 +1,121 @@
-use askama::filters::urlencode;
+use std::{net::SocketAddr, sync::Arc};
+
 use askama_axum::IntoResponse;
 use axum::{
-    extract::Query,
-    http::{header::CACHE_CONTROL, HeaderMap, HeaderValue, StatusCode},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, USER_AGENT},
+        HeaderMap, HeaderValue, StatusCode,
+    },
     response::Redirect,
-    Json,
+    Extension, Json,
 };
 use axum_extra::extract::PrivateCookieJar;
-use cookie::Cookie;
+use cookie::{time, Cookie, SameSite};
 use serde_json::json;
 use tracing::error;
 
 use crate::{
-    middleware::auth::build_expired_cookie,
+    config::Config,
+    database::timed,
+    middleware::{
+        auth::{build_expired_cookie, refresh_cookie_sid, session_cookies, signed_claims},
+        public_url::PublicUrl,
+    },
     models,
-    utils::{discord, env, jwt, strings},
+    utils::{
+        client_ip, jwt,
+        oauth::{LoginError, LoginFlow, LoginProviders},
+    },
 };
 
-fn redirect_with_cache_control(url: &str) -> impl IntoResponse {
-    let mut response = Redirect::temporary(url).into_response();
-    response.headers_mut().insert(
-        CACHE_CONTROL,
-        HeaderValue::from_static("max-age=10, public"),
-    );
-    response
+/// Holds the `LoginFlow` between the login redirect and the callback
+const LOGIN_FLOW_COOKIE: &str = "login_flow";
+const LOGIN_FLOW_MINUTES: i64 = 10;
+
+fn count_login_failure(reason: &'static str) {
+    metrics::counter!("shidou_logins_total", "result" => "failure", "reason" => reason)
+        .increment(1);
 }
 
-pub async fn get_login_redirect(headers: HeaderMap) -> impl IntoResponse {
-    let discord_config = env::get_discord_config();
-    let host = strings::get_host_header(&headers, true);
-    let redirect_uri = format!("{}/auth/callback", host);
-    let redirect_uri = urlencode(redirect_uri).expect("failed to urlencode redirect_uri");
-    let scopes = ["identify", "guilds"];
-
-    let url = format!(
-        "https://discord.com/oauth2/authorize?client_id={}&response_type=code&redirect_uri={}&scope={}",
-        discord_config.client_id,
-        redirect_uri,
-        scopes.join("%20"),
+/// Start logging in with the provider in the path, or with the first one configured
+pub async fn get_login_redirect(
+    State(config): State<Arc<Config>>,
+    State(providers): State<Arc<LoginProviders>>,
+    Extension(public_url): Extension<PublicUrl>,
+    provider: Option<Path<String>>,
+) -> impl IntoResponse {
+    let provider = match provider {
+        Some(Path(id)) => providers.get(&id),
+        None => providers.default_provider(),
+    };
+    let provider = match provider {
+        Some(provider) => provider,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "Unknown login provider" })),
+            )
+                .into_response()
+        }
+    };
+
+    let flow = LoginFlow::new(provider.id());
+    let redirect_uri = format!("{}/auth/callback", public_url.base);
+    let url = match provider.authorize_url(&flow, &redirect_uri).await {
+        Ok(url) => url,
+        Err(e) => {
+            error!("Failed to start login with {}: {:?}", provider.id(), e);
+            count_login_failure("provider");
+            return (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    };
+
+    let jar = PrivateCookieJar::new(config.cookie_key.clone()).add(
+        Cookie::build((LOGIN_FLOW_COOKIE, serde_json::to_string(&flow).unwrap()))
+            .path("/auth")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::minutes(LOGIN_FLOW_MINUTES)),
     );
 
-    redirect_with_cache_control(&url).into_response()
+    // every login gets its own state, so this must never come from a cache
+    let mut response = Redirect::temporary(&url).into_response();
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    (jar, response).into_response()
 }
 
-pub async fn logout() -> impl axum::response::IntoResponse {
+pub async fn logout(
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+) -> impl axum::response::IntoResponse {
+    // the tokens could still be replayed if only the browser forgot them
+    // an expired access token still names its session, and the refresh token does too
+    let sid = signed_claims(&headers, &config)
+        .map(|claims| claims.sid)
+        .or_else(|| refresh_cookie_sid(&headers, &config));
+    if let Some(sid) = sid {
+        if let Err(err) = models::session::delete_session_by_sid(&sid).await {
+            error!("Failed to end session on logout: {:?}", err);
+        }
+    }
+
     let mut response = Redirect::temporary("/").into_response();
-    response.headers_mut().insert(
-        CACHE_CONTROL,
-        HeaderValue::from_static("max-age=10, public"),
-    );
+    // the response sets or clears login cookies, a shared cache must not hand it to anyone else
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
 
-    let key = cookie::Key::from(env::get_cookie_encryption_key().as_bytes());
-    let jar = PrivateCookieJar::new(key)
+    let jar = PrivateCookieJar::new(config.cookie_key.clone())
         .add(build_expired_cookie("auth_token"))
+        .add(build_expired_cookie("refresh_token"))
         .add(build_expired_cookie("user_id"));
 
     for cookie in jar.iter() {
@@ -68,118 +131,144 @@ pub async fn logout() -> impl axum::response::IntoResponse {
 #[derive(serde::Deserialize)]
 pub struct CallbackQuery {
     pub code: Option<String>,
+    pub state: Option<String>,
     pub error: Option<String>,
     pub error_description: Option<String>,
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct TokenResponse {
-    pub access_token: String,
-    pub token_type: String,
-    pub expires_in: i64,
-    pub refresh_token: String,
-    pub scope: String,
-}
-
-pub async fn callback(headers: HeaderMap, Query(query): Query<CallbackQuery>) -> impl IntoResponse {
+pub async fn callback(
+    State(config): State<Arc<Config>>,
+    State(providers): State<Arc<LoginProviders>>,
+    Extension(public_url): Extension<PublicUrl>,
+    jar: PrivateCookieJar,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<CallbackQuery>,
+) -> impl IntoResponse {
     if query.error.is_some() {
-        error!("Discord OAuth error: {:?}", query.error_description);
+        error!("OAuth error: {:?}", query.error_description);
+        count_login_failure("oauth_error");
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": query.error_description.unwrap() })),
+            Json(json!({ "error": query.error_description.unwrap_or_default() })),
         )
             .into_response();
     }
-    let code = query.code.expect("code not found");
-
-    let discord_config = env::get_discord_config();
-    let host = strings::get_host_header(&headers, true);
-    let redirect_uri = format!("{}/auth/callback", host);
-
-    let client = reqwest::Client::new();
-
-    let res = client
-        .post("https://discord.com/api/oauth2/token")
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&[
-            ("client_id", discord_config.client_id),
-            ("client_secret", discord_config.client_secret),
-            ("redirect_uri", redirect_uri),
-            ("code", code),
-            ("scope", "identify+guilds".to_string()),
-            ("grant_type", "authorization_code".to_string()),
-        ])
-        .send()
-        .await;
-
-    if let Err(e) = res {
-        error!("Failed to get token: {:?}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": e.to_string() })),
-        )
-            .into_response();
-    }
-    let res = res.unwrap();
 
-    if !res.status().is_success() {
-        let err_text = res.text().await.unwrap();
-        error!("Failed to get token: {:?}", err_text);
+    // the state must match the login this browser started, or someone else's code is being slipped in
+    let flow = jar
+        .get(LOGIN_FLOW_COOKIE)
+        .and_then(|cookie| serde_json::from_str::<LoginFlow>(cookie.value()).ok())
+        .filter(|flow| query.state.as_deref() == Some(flow.state.as_str()));
+    let jar = jar.remove(Cookie::build(LOGIN_FLOW_COOKIE).path("/auth"));
+    let (flow, code) = match (flow, query.code) {
+        (Some(flow), Some(code)) => (flow, code),
+        _ => {
+            count_login_failure("state");
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Login expired or was started in another browser, try again" })),
+            )
+                .into_response();
+        }
+    };
+    let provider = match providers.get(&flow.provider) {
+        Some(provider) => provider,
+        None => {
+            count_login_failure("provider");
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Unknown login provider" })),
+            )
+                .into_response();
+        }
+    };
+
+    let redirect_uri = format!("{}/auth/callback", public_url.base);
+    let identity = match provider.identify(&code, &flow, &redirect_uri).await {
+        Ok(identity) => identity,
+        Err(err) => {
+            count_login_failure(err.reason());
+            return match err {
+                LoginError::Forbidden { message, .. } => {
+                    (StatusCode::FORBIDDEN, Json(json!({ "error": message }))).into_response()
+                }
+                LoginError::TokenExchange(e) | LoginError::UserInfo(e) => {
+                    error!("Failed to log in with {}: {:?}", provider.id(), e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!({ "error": e.to_string() })),
+                    )
+                        .into_response()
+                }
+            };
+        }
+    };
+
+    let upserted_user = timed(
+        "upsert_identity_user",
+        models::identity::upsert_identity_user(provider.id(), &identity),
+    )
+    .await;
+    if let Err(e) = upserted_user {
+        error!("Failed to upsert user: {:?}", e);
+        count_login_failure("database");
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": err_text })),
+            Json(json!({ "error": e.to_string() })),
         )
             .into_response();
     }
+    let upserted_user = upserted_user.unwrap();
+    tracing::Span::current().record("user_id", upserted_user.id);
 
-    let token_response = res
-        .json::<TokenResponse>()
-        .await
-        .expect("failed to parse token response");
-
-    let user_info = discord::get_user_info_by_token(&token_response.access_token).await;
-    if let Err(e) = user_info {
-        error!("Failed to get user info: {:?}", e);
+    if upserted_user.disabled {
+        count_login_failure("disabled");
         return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": e.to_string() })),
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Your account has been disabled" })),
         )
             .into_response();
     }
-    let user_info = user_info.unwrap();
 
-    if !discord_config.guilds.is_empty() {
-        if !user_info.has_any_guild(discord_config.guilds.as_ref()) {
-            return (
-                StatusCode::FORBIDDEN,
-                Json(json!({ "error": "You are not a member of an allowed Discord guild" })),
-            )
-                .into_response();
-        }
-    }
+    let mut response = Redirect::temporary("/").into_response();
+    // the response sets or clears login cookies, a shared cache must not hand it to anyone else
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
 
-    let upserted_user = models::user::upsert_user(&user_info.id, &user_info.username).await;
-    if let Err(e) = upserted_user {
-        error!("Failed to upsert user: {:?}", e);
+    let client_ip = client_ip::get_client_ip(&headers, peer.ip(), &config.trusted_proxies);
+    let session = timed(
+        "create_session",
+        models::session::create_session(
+            upserted_user.id,
+            config.session.refresh_token_ttl,
+            headers.get(USER_AGENT).and_then(|ua| ua.to_str().ok()),
+            &client_ip.to_string(),
+        ),
+    )
+    .await;
+    if let Err(e) = session {
+        error!("Failed to create session: {:?}", e);
+        count_login_failure("database");
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({ "error": e.to_string() })),
         )
             .into_response();
     }
-    let upserted_user = upserted_user.unwrap();
+    let (sid, refresh_token) = session.unwrap();
 
-    let mut response = Redirect::temporary("/").into_response();
-    response.headers_mut().insert(
-        CACHE_CONTROL,
-        HeaderValue::from_static("max-age=10, public"),
+    let jwt = jwt::create_jwt(
+        upserted_user.id,
+        &sid,
+        config.session.access_token_ttl,
+        &config.jwt.encoding,
     );
-
-    let key = cookie::Key::from(env::get_cookie_encryption_key().as_bytes());
-    let jwt = jwt::create_jwt(upserted_user.id);
     if jwt.is_err() {
         let err_text = jwt.err().unwrap();
         error!("Failed to create JWT: {:?}", err_text);
+        count_login_failure("jwt");
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({ "error": err_text.to_string() })),
@@ -188,16 +277,14 @@ pub async fn callback(headers: HeaderMap, Query(query): Query<CallbackQuery>) ->
     }
     let jwt = jwt.unwrap();
 
-    let jar = PrivateCookieJar::new(key)
-        .add(Cookie::build(("auth_token", jwt)).path("/"))
-        .add(Cookie::build(("user_id", upserted_user.id.to_string())).path("/"));
-
-    for cookie in jar.iter() {
+    for cookie in session_cookies(jwt, Some(refresh_token), &config) {
         let cookie_value = cookie.encoded().to_string();
         response
             .headers_mut()
             .append("Set-Cookie", cookie_value.parse().unwrap());
     }
 
-    (jar, Redirect::temporary("/")).into_response()
+    metrics::counter!("shidou_logins_total", "result" => "success", "reason" => "").increment(1);
+
+    (jar, response).into_response()
 }
@@ -1 +1,2 @@
 pub mod redirect_url_input;
+pub mod sessions;
@@ -0,0 +1,41 @@
+use crate::{middleware::auth::Principal, models::session, utils::user_agent};
+
+pub async fn get(principal: Principal) -> impl axum::response::IntoResponse {
+    let current = principal.session.clone();
+    let sessions = session::get_sessions(principal.user_id)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("Failed to get sessions: {:?}", err);
+            vec![]
+        });
+
+    SessionList {
+        sessions: sessions
+            .into_iter()
+            .map(|session| {
+                let client = user_agent::parse(session.user_agent.as_deref().unwrap_or_default());
+                SessionItem {
+                    id: session.id,
+                    device: format!("{} {}", client.os.as_str(), client.device.as_str()),
+                    ip: session.ip.unwrap_or_default(),
+                    last_seen: session.last_seen_utc.unwrap_or_default(),
+                    current: current.as_deref() == Some(session.sid.as_str()),
+                }
+            })
+            .collect(),
+    }
+}
+
+struct SessionItem {
+    id: i64,
+    device: String,
+    ip: String,
+    last_seen: String,
+    current: bool,
+}
+
+#[derive(askama::Template)]
+#[template(path = "components/sessions.html")]
+struct SessionList {
+    sessions: Vec<SessionItem>,
+}
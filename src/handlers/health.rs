@@ -0,0 +1,123 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{config::Config, database};
+
+/// How long the readiness query may take before the database counts as down
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The process is up and serving requests, nothing more
+pub async fn livez() -> impl IntoResponse {
+    "Ok"
+}
+
+#[derive(Serialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    #[default]
+    Ok,
+    Degraded,
+}
+
+#[derive(Serialize, Default)]
+struct ComponentStatus {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_sync_utc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync_age_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Components {
+    database: ComponentStatus,
+    replica: ComponentStatus,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: Status,
+    components: Components,
+}
+
+/// Whether this machine should get traffic, 503 when the database doesn't answer or the replica
+/// hasn't synced with the remote database for longer than `max_sync_age`
+pub async fn readyz(State(config): State<Arc<Config>>) -> impl IntoResponse {
+    let started = Instant::now();
+    let ping = tokio::time::timeout(DATABASE_TIMEOUT, database::ping()).await;
+    let latency_ms = Some(started.elapsed().as_millis());
+    let database = match ping {
+        Ok(Ok(())) => ComponentStatus {
+            latency_ms,
+            ..Default::default()
+        },
+        Ok(Err(err)) => ComponentStatus {
+            status: Status::Degraded,
+            latency_ms,
+            error: Some(format!("{:#}", err)),
+            ..Default::default()
+        },
+        Err(_) => ComponentStatus {
+            status: Status::Degraded,
+            latency_ms,
+            error: Some(format!("No answer within {:?}", DATABASE_TIMEOUT)),
+            ..Default::default()
+        },
+    };
+
+    let max_sync_age = config.database.max_sync_age;
+    let replica = match database::last_sync() {
+        Some(last_sync) => {
+            let sync_age_seconds = (Utc::now() - last_sync).num_seconds();
+            let stale = sync_age_seconds > max_sync_age.as_secs() as i64;
+            ComponentStatus {
+                status: match stale {
+                    true => Status::Degraded,
+                    false => Status::Ok,
+                },
+                last_sync_utc: Some(last_sync.format("%Y-%m-%d %H:%M:%S").to_string()),
+                sync_age_seconds: Some(sync_age_seconds),
+                error: stale.then(|| {
+                    format!(
+                        "No successful sync with the remote database in the last {:?}",
+                        max_sync_age
+                    )
+                }),
+                ..Default::default()
+            }
+        }
+        None => ComponentStatus {
+            status: Status::Degraded,
+            error: Some("The replica has never synced".to_string()),
+            ..Default::default()
+        },
+    };
+
+    let healthy = database.status == Status::Ok && replica.status == Status::Ok;
+    let (code, status) = match healthy {
+        true => (StatusCode::OK, Status::Ok),
+        false => (StatusCode::SERVICE_UNAVAILABLE, Status::Degraded),
+    };
+
+    (
+        code,
+        Json(Readiness {
+            status,
+            components: Components { database, replica },
+        }),
+    )
+}
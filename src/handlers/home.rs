@@ -1,24 +1,34 @@
+use std::sync::Arc;
+
 use askama_axum::IntoResponse;
-use axum::http::HeaderMap;
+use axum::{extract::State, Extension};
 
-use crate::middleware::auth::check_auth;
+use crate::{
+    middleware::{auth::Principal, public_url::PublicUrl},
+    utils::oauth::LoginProviders,
+};
 
-pub async fn get(headers: HeaderMap) -> impl axum::response::IntoResponse {
-    match check_auth(&headers).await.is_some() {
+pub async fn get(
+    State(providers): State<Arc<LoginProviders>>,
+    Extension(public_url): Extension<PublicUrl>,
+    principal: Option<Principal>,
+) -> impl axum::response::IntoResponse {
+    match principal.is_some() {
         true => {
-            let host = match headers
-                .get("host")
-                .expect("host header not found")
-                .to_str()
-                .unwrap()
-            {
-                "localhost:8080" => "http://localhost:8080/".to_string(),
-                h => format!("https://{}/", h),
-            };
+            let host = format!("{}/", public_url.base);
 
             DashboardPage { host }.into_response()
         }
-        false => LoginPage {}.into_response(),
+        false => LoginPage {
+            providers: providers
+                .iter()
+                .map(|provider| LoginButton {
+                    id: provider.id(),
+                    name: provider.display_name().to_string(),
+                })
+                .collect(),
+        }
+        .into_response(),
     }
 }
 
@@ -30,4 +40,11 @@ struct DashboardPage {
 
 #[derive(askama::Template)]
 #[template(path = "pages/login.html")]
-struct LoginPage {}
+struct LoginPage {
+    providers: Vec<LoginButton>,
+}
+
+struct LoginButton {
+    id: &'static str,
+    name: String,
+}
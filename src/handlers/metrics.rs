@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    response::IntoResponse,
+};
+use tokio_util::task::TaskTracker;
+
+use crate::{config::Config, models::redirect, telemetry, utils::strings::secrets_match};
+
+/// Prometheus scrape endpoint, behind `METRICS_TOKEN` when one is configured
+pub async fn get(
+    State(config): State<Arc<Config>>,
+    State(tasks): State<TaskTracker>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(token) = &config.metrics.token {
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !bearer.is_some_and(|bearer| secrets_match(bearer, token)) {
+            return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+        }
+    }
+
+    // background tasks are only spawned to flush visits, so whatever is still running is the backlog
+    metrics::gauge!("shidou_visit_flush_backlog").set(tasks.len() as f64);
+    if let Some(ratio) = redirect::cache_hit_ratio() {
+        metrics::gauge!("shidou_redirect_cache_hit_ratio").set(ratio);
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        telemetry::render_metrics(),
+    )
+        .into_response()
+}
@@ -1,6 +1,9 @@
 pub mod api;
 pub mod auth;
 pub mod components;
+pub mod health;
 pub mod home;
+pub mod metrics;
 pub mod not_found;
+pub mod qr;
 pub mod redirect;
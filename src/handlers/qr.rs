@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use askama_axum::IntoResponse;
+use axum::{
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        HeaderValue, StatusCode,
+    },
+    Json,
+};
+use serde_json::json;
+use tracing::error;
+
+use crate::{
+    middleware::public_url::PublicUrl,
+    models,
+    utils::qr::{self, QrOptions},
+};
+
+/// Render the QR code for the short link behind `key`, shared by `/{key}.qr` and the API
+pub async fn qr_response(
+    key: &str,
+    public_url: &PublicUrl,
+    params: &HashMap<String, String>,
+) -> axum::response::Response {
+    let options = match QrOptions::from_params(params) {
+        Ok(options) => options,
+        Err(err) => {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": err }))).into_response()
+        }
+    };
+
+    let redirects = models::redirect::get_all_redirects().await;
+    let exists = match &redirects {
+        Ok(redirects) => redirects.iter().any(|r| r.key == key),
+        Err(_) => false,
+    };
+    if !exists {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    }
+
+    let short_url = format!("{}/{}", public_url.base, key);
+    match qr::render(&short_url, &options) {
+        Ok(image) => {
+            let mut response = image.into_response();
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(options.format.content_type()),
+            );
+            response.headers_mut().insert(
+                CACHE_CONTROL,
+                HeaderValue::from_static("max-age=180, public"),
+            );
+            response
+        }
+        Err(err) => {
+            error!("Failed to render QR code: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    }
+}
@@ -1,11 +1,33 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
 use askama_axum::IntoResponse;
 use axum::{
-    extract::Path,
-    http::{header::CACHE_CONTROL, HeaderValue, StatusCode},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, SET_COOKIE, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
     response::Redirect,
+    Extension,
 };
+use axum_extra::extract::CookieJar;
+use cookie::{time, Cookie, SameSite};
+use tokio_util::task::TaskTracker;
+use tracing::error;
 
-use crate::models::{self, redirect::inc_visits};
+use crate::{
+    config::Config,
+    database::timed,
+    handlers,
+    middleware::public_url::PublicUrl,
+    models::{
+        self,
+        redirect::{inc_visits, RedirectRow},
+        target::{inc_clicks, pick_weighted},
+        visit::{record_visit, NewVisit},
+    },
+    utils::{client_ip, geoip, language, user_agent},
+};
 
 fn redirect_with_cache_control(url: &str) -> impl IntoResponse {
     let mut response = Redirect::temporary(url).into_response();
@@ -13,23 +35,261 @@ fn redirect_with_cache_control(url: &str) -> impl IntoResponse {
         CACHE_CONTROL,
         HeaderValue::from_static("max-age=180, public"),
     );
+    // the target depends on who is asking, so shared caches must not mix clients up
+    response.headers_mut().insert(
+        VARY,
+        HeaderValue::from_static("User-Agent, Accept-Language"),
+    );
     response
 }
 
-pub async fn get(Path(path): Path<String>) -> impl axum::response::IntoResponse {
-    let redirects = models::redirect::get_all_redirects().await;
-    let redirect = match &redirects {
-        Ok(redirects) => redirects.iter().find(|r| r.key == path),
+pub async fn get(
+    State(config): State<Arc<Config>>,
+    State(tasks): State<TaskTracker>,
+    Extension(public_url): Extension<PublicUrl>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl axum::response::IntoResponse {
+    if let Some(key) = path.strip_suffix(".qr") {
+        return handlers::qr::qr_response(key, &public_url, &params).await;
+    }
+
+    // a trailing `+` or a `?preview` query asks for the preview page instead of the redirect
+    let (key, preview) = match path.strip_suffix('+') {
+        Some(key) => (key.to_string(), true),
+        None => (path, params.contains_key("preview")),
+    };
+    // `?go` is the Continue button of the interstitial, it takes the visitor on through the usual routing
+    let interstitial_passed = params.contains_key("go");
+
+    let redirect = models::redirect::get_cached_redirect(&key).await;
+    let redirect = match &redirect {
+        Ok(redirect) => redirect.as_deref(),
         Err(_) => None,
     };
+    let result = match redirect {
+        Some(_) => "hit",
+        None => "miss",
+    };
+    metrics::counter!("shidou_redirects_total", "result" => result).increment(1);
+
     match redirect {
+        Some(redirect) if preview => preview_page(redirect).await.into_response(),
+        // crawlers come before the interstitial, they are after the OpenGraph tags and would never press Continue
+        Some(redirect) if user_agent::is_unfurl_bot(user_agent::get_user_agent(&headers)) => {
+            let mut response = unfurl_page(redirect, &public_url).into_response();
+            response
+                .headers_mut()
+                .insert(VARY, HeaderValue::from_static("User-Agent"));
+            response
+        }
+        Some(redirect)
+            if redirect.interstitial
+                && !interstitial_passed
+                && is_external(&redirect.url, &public_url) =>
+        {
+            preview_page(redirect).await.into_response()
+        }
         Some(redirect) => {
+            let client_ip = client_ip::get_client_ip(&headers, peer.ip(), &config.trusted_proxies);
+            let country = geoip::get_country_code(client_ip);
+            let target = resolve_target(redirect, &headers, country.as_deref()).await;
             let key = redirect.key.clone();
-            tokio::spawn(async move {
-                let _ = inc_visits(&key).await;
+            let visit = NewVisit {
+                redirect_id: redirect.id,
+                locale: target.locale,
+                target_id: target.variant,
+                country,
+            };
+            tasks.spawn(async move {
+                let _ = timed("inc_visits", inc_visits(&key)).await;
+                if let Some(target_id) = visit.target_id {
+                    let _ = timed("inc_clicks", inc_clicks(target_id)).await;
+                }
+                if let Err(err) = timed("record_visit", record_visit(&visit)).await {
+                    error!("Failed to record visit for {}: {:?}", key, err);
+                }
             });
-            redirect_with_cache_control(&redirect.url).into_response()
+
+            let mut response = redirect_with_cache_control(&target.url).into_response();
+            if let Some(variant) = target.variant {
+                // keep the visitor on the same variant
+                let cookie = Cookie::build((variant_cookie_name(redirect.id), variant.to_string()))
+                    .path("/")
+                    .max_age(time::Duration::days(30))
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .build();
+                if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+                    response.headers_mut().append(SET_COOKIE, value);
+                }
+            }
+            if target.private {
+                response
+                    .headers_mut()
+                    .insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+            }
+            response
         }
         None => (StatusCode::NOT_FOUND, "Not Found").into_response(),
     }
 }
+
+#[derive(Default)]
+struct Target {
+    url: String,
+    locale: Option<String>,
+    /// Id of the split variant the client was assigned to
+    variant: Option<i64>,
+    /// The target depends on more than the headers in `Vary`, so shared caches must not keep it
+    private: bool,
+}
+
+fn variant_cookie_name(redirect_id: i64) -> String {
+    format!("shidou_variant_{}", redirect_id)
+}
+
+/// Pick the url this client should be sent to, falling back to the redirect's default url
+/// Device and country rules win over the language map, which wins over a weighted split, which wins over the default
+async fn resolve_target(
+    redirect: &RedirectRow,
+    headers: &HeaderMap,
+    country: Option<&str>,
+) -> Target {
+    let client = user_agent::parse(user_agent::get_user_agent(headers));
+    let mut geo_routed = false;
+
+    match timed("get_rules", models::rule::get_rules(redirect.id)).await {
+        Ok(rules) => {
+            geo_routed = rules.iter().any(|rule| rule.country.is_some());
+            if let Some(rule) = rules.iter().find(|rule| rule.matches(&client, country)) {
+                return Target {
+                    url: rule.url.clone(),
+                    private: geo_routed,
+                    ..Default::default()
+                };
+            }
+        }
+        Err(err) => error!(
+            "Failed to get rules for redirect {}: {:?}",
+            redirect.key, err
+        ),
+    }
+
+    match timed("get_locales", models::locale::get_locales(redirect.id)).await {
+        Ok(locales) if !locales.is_empty() => {
+            let available: Vec<&str> = locales.iter().map(|l| l.language.as_str()).collect();
+            if let Some(language) = language::negotiate(headers, &available) {
+                let locale = locales.iter().find(|l| l.language == language);
+                if let Some(locale) = locale {
+                    return Target {
+                        url: locale.url.clone(),
+                        locale: Some(locale.language.clone()),
+                        private: geo_routed,
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!(
+            "Failed to get locales for redirect {}: {:?}",
+            redirect.key, err
+        ),
+    }
+
+    match timed("get_targets", models::target::get_targets(redirect.id)).await {
+        Ok(targets) if !targets.is_empty() => {
+            // returning visitors stay on their variant as long as it is still running
+            let sticky = CookieJar::from_headers(headers)
+                .get(&variant_cookie_name(redirect.id))
+                .and_then(|cookie| cookie.value().parse::<i64>().ok())
+                .and_then(|id| targets.iter().find(|t| t.id == id && t.weight > 0));
+            if let Some(variant) = sticky.or_else(|| pick_weighted(&targets)) {
+                return Target {
+                    url: variant.url.clone(),
+                    variant: Some(variant.id),
+                    private: true,
+                    ..Default::default()
+                };
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!(
+            "Failed to get targets for redirect {}: {:?}",
+            redirect.key, err
+        ),
+    }
+
+    Target {
+        url: redirect.url.clone(),
+        private: geo_routed,
+        ..Default::default()
+    }
+}
+
+async fn preview_page(redirect: &RedirectRow) -> PreviewPage {
+    let owner = models::user::get_user_by_id(redirect.created_by)
+        .await
+        .map(|user| user.discord_username)
+        .ok();
+
+    PreviewPage {
+        key: redirect.key.clone(),
+        url: redirect.url.clone(),
+        continue_url: format!("/{}?go", redirect.key),
+        owner,
+        created: redirect.created_utc.format("%B %-d, %Y").to_string(),
+        visits: redirect.visits,
+    }
+}
+
+fn unfurl_page(redirect: &RedirectRow, public_url: &PublicUrl) -> UnfurlPage {
+    UnfurlPage {
+        title: redirect
+            .og_title
+            .clone()
+            .unwrap_or_else(|| redirect.key.clone()),
+        description: redirect.og_description.clone(),
+        image: redirect.og_image.clone(),
+        short_url: format!("{}/{}", public_url.base, redirect.key),
+        url: redirect.url.clone(),
+    }
+}
+
+/// Whether the redirect target lives on a different host than the one serving the short link
+fn is_external(url: &str, public_url: &PublicUrl) -> bool {
+    let target_host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()));
+    let request_host = public_url.host.split(':').next().unwrap_or_default();
+
+    match target_host {
+        Some(target_host) => !target_host.eq_ignore_ascii_case(request_host),
+        None => true,
+    }
+}
+
+#[derive(askama::Template)]
+#[template(path = "pages/preview.html")]
+struct PreviewPage {
+    key: String,
+    url: String,
+    /// Back through the short link, so the visit is counted and rules and variants still apply
+    continue_url: String,
+    owner: Option<String>,
+    created: String,
+    visits: u64,
+}
+
+#[derive(askama::Template)]
+#[template(path = "pages/unfurl.html")]
+struct UnfurlPage {
+    title: String,
+    description: Option<String>,
+    image: Option<String>,
+    short_url: String,
+    url: String,
+}
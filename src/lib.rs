@@ -0,0 +1,4 @@
+//! Request and response types of the shidou API and the helpers shared by the server and `shidou-cli`
+
+pub mod random;
+pub mod types;
@@ -1,57 +1,236 @@
+use std::{future::IntoFuture, path::PathBuf, sync::Arc};
+
 use anyhow::Context;
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
 use tokio::net::TcpListener;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tower_http::compression::{
     predicate::NotForContentType, CompressionLayer, DefaultPredicate, Predicate,
 };
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+use config::{AppState, Config, LogFormat};
+use utils::oauth::LoginProviders;
 
+mod commands;
+mod config;
 mod database;
 mod handlers;
 mod middleware;
 mod models;
 mod routes;
+mod shutdown;
+mod telemetry;
 mod utils;
 
+#[derive(Parser)]
+#[command(version, about = "Shidou URL shortener")]
+struct Cli {
+    /// TOML config file, defaults to `shidou.toml` when it exists
+    /// Settings from the environment take precedence over the file
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server, the default when no command is given
+    Serve,
+    /// Create missing tables and columns, then exit
+    Migrate,
+    /// Validate the environment without starting the server
+    CheckConfig,
+    /// Manage users
+    #[command(subcommand)]
+    User(commands::user::UserCommand),
+    /// Manage the keys that sign login tokens
+    #[command(subcommand)]
+    Keys(commands::keys::KeysCommand),
+    /// Add and remove redirects without the web UI
+    #[command(subcommand)]
+    Redirect(commands::redirect::RedirectCommand),
+    /// Import redirects from a CSV, shidou JSON, YOURLS or Bitly export
+    Import(commands::import::ImportArgs),
+    /// Export all redirects, users and analytics as JSON Lines or CSV
+    Export(commands::backup::ExportArgs),
+    /// Restore an export into an empty database
+    Restore(commands::backup::RestoreArgs),
+    /// Run a stand-in OpenID Connect issuer on localhost, to try the OIDC login without a real one
+    MockOidc(commands::mock_oidc::MockOidcArgs),
+}
+
+impl Command {
+    /// Checking the config works before the database is set up
+    fn needs_database(&self) -> bool {
+        !matches!(self, Command::CheckConfig)
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
+    let cli = Cli::parse();
 
-    let env_filter = tracing_subscriber::EnvFilter::builder()
-        .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
-        .from_env_lossy();
-    tracing_subscriber::registry()
-        .with(env_filter)
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        // keys are generated before there is a config to load them from
+        Command::Keys(command) => {
+            telemetry::init_logging(LogFormat::default(), None)?;
+            commands::keys::run(command)
+        }
+        // the issuer stands in for a provider shidou's config points at, it needs none itself
+        Command::MockOidc(args) => {
+            telemetry::init_logging(LogFormat::default(), None)?;
+            commands::mock_oidc::run(args).await
+        }
+        command => {
+            let config = Config::load(cli.config)?;
+            telemetry::init_logging(config.log_format, config.otlp_endpoint.as_deref())?;
+            run(command, config).await
+        }
+    };
 
-    database::init_tables()
-        .await
-        .context("error while initializing database tables")?;
+    telemetry::shutdown_tracing();
+    result
+}
+
+async fn run(command: Command, config: Config) -> anyhow::Result<()> {
+    if command.needs_database() {
+        database::connect(&config.database)
+            .await
+            .context("error while connecting to the database")?;
+        database::init_tables()
+            .await
+            .context("error while initializing database tables")?;
+    }
 
-    let port = crate::utils::env::get_port();
+    match command {
+        Command::Serve => serve(config).await,
+        Command::Migrate => {
+            tracing::info!("Database tables are up to date");
+            Ok(())
+        }
+        Command::CheckConfig => commands::check_config::run(&config),
+        Command::User(command) => commands::user::run(command, &config).await,
+        Command::Redirect(command) => commands::redirect::run(command).await,
+        Command::Import(args) => commands::import::run(args).await,
+        Command::Export(args) => commands::backup::export(args).await,
+        Command::Restore(args) => commands::backup::restore(args).await,
+        // both run from `main` without loading a config
+        Command::Keys(_) | Command::MockOidc(_) => unreachable!("command needs no config"),
+    }
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    utils::geoip::init(config.geoip_db_path.as_deref());
+    telemetry::init_metrics()?;
+    if config
+        .discord
+        .as_ref()
+        .is_some_and(|discord| discord.guilds.is_empty())
+    {
+        tracing::warn!("DISCORD_ALLOWED_GUILDS not set, allowing all Discord users to login");
+    }
+    if config
+        .github
+        .as_ref()
+        .is_some_and(|github| github.orgs.is_empty() && github.teams.is_empty())
+    {
+        tracing::warn!(
+            "GITHUB_ALLOWED_ORGS and GITHUB_ALLOWED_TEAMS not set, allowing all GitHub users to login"
+        );
+    }
+    if config.trusted_proxies.is_empty()
+        && (config.rate_limit.redirect.is_some() || config.rate_limit.auth.is_some())
+    {
+        tracing::warn!(
+            "TRUSTED_PROXIES not set, rate limits are per connecting address, behind a proxy all clients share one"
+        );
+    }
+
+    let port = config.port;
+    let metrics_port = config.metrics.port;
+    let sync_interval = config.database.sync_interval;
+    let shutdown_timeout = config.shutdown_timeout;
+    let tasks = TaskTracker::new();
+    let state = AppState {
+        login_providers: Arc::new(LoginProviders::from_config(&config)),
+        config: Arc::new(config),
+        tasks: tasks.clone(),
+    };
     let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
 
     let listener = TcpListener::bind(&addr)
         .await
         .context("error while binding to port")?;
-    let app = routes::main_router()
-        // add request url to response for logger to use
+    let app = routes::main_router(state.clone())
+        // count requests by the route they matched
         .layer(axum::middleware::from_fn(
-            middleware::logging::uri_middleware,
+            middleware::metrics::metrics_middleware,
         ))
-        // add custom logging format
-        .layer(TraceLayer::new_for_http().on_response(middleware::logging::logging_middleware))
+        // log every request inside a span tagged with its request id
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::logging::access_log_middleware,
+        ))
+        // keep the X-Request-Id of the proxy or make one up, and send it back
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         // add gzip compression
         .layer(CompressionLayer::new().gzip(true).compress_when(
             DefaultPredicate::new().and(NotForContentType::new("application/json")),
         ));
 
-    axum::serve(listener, app.into_make_service())
-        .await
-        .context("error while starting API server")?;
+    // the drain timeout starts with the signal, it covers open connections and background tasks together
+    let shutdown = CancellationToken::new();
+    let deadline = tokio::spawn(shutdown::deadline_on_signal(
+        shutdown.clone(),
+        shutdown_timeout,
+    ));
+
+    // keep the replica current, and its sync age honest for /readyz
+    tokio::spawn(database::sync_periodically(sync_interval, shutdown.clone()));
+
+    if let Some(metrics_port) = metrics_port {
+        let metrics_addr = std::net::SocketAddr::from(([0, 0, 0, 0], metrics_port));
+        let metrics_listener = TcpListener::bind(&metrics_addr)
+            .await
+            .context("error while binding to metrics port")?;
+        let metrics_app = routes::metrics_router(state);
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let result = axum::serve(metrics_listener, metrics_app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await;
+            if let Err(err) = result {
+                tracing::error!("Metrics server failed: {:?}", err);
+            }
+        });
+        tracing::info!("Metrics served on {}", metrics_addr);
+    }
+
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+    .into_future();
+    let drain_timeout = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+
+    tracing::info!("Server started on {}", addr);
+    tokio::select! {
+        result = server => result.context("error while starting API server")?,
+        _ = drain_timeout => tracing::warn!("Drain timeout reached, dropping open connections"),
+    }
+
+    let deadline = deadline.await.context("shutdown signal handler failed")?;
+    shutdown::finish(tasks, deadline).await;
 
-    tracing::info!("Server srarted");
+    tracing::info!("Server stopped");
     anyhow::Ok(())
 }
@@ -1,67 +1,402 @@
+use std::{sync::Arc, time::Duration};
+
 use axum::{
-    extract::Request,
-    http::{HeaderMap, StatusCode},
+    async_trait,
+    extract::{FromRef, FromRequestParts, Request, State},
+    http::{
+        header::{AUTHORIZATION, CACHE_CONTROL, COOKIE, SET_COOKIE},
+        request::Parts,
+        HeaderMap, HeaderValue, StatusCode,
+    },
     middleware::Next,
     response::Response,
+    Extension,
 };
 
 use axum_extra::extract::PrivateCookieJar;
-use cookie::{time, Cookie, Key};
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use tracing::trace;
+use chrono::Utc;
+use cookie::{time, Cookie, CookieJar, SameSite};
+use jsonwebtoken::{decode, Algorithm, Validation};
+use tracing::{debug, error, trace, warn};
+
+use crate::{
+    config::Config,
+    database::timed,
+    models::{
+        self,
+        session::{refresh_token_sid, Rotation},
+        user::{ROLE_ADMIN, ROLE_VIEWER},
+    },
+    utils::jwt::{self, JWT},
+};
 
-use crate::utils::{env, jwt::JWT};
+const AUTH_COOKIE: &str = "auth_token";
+const REFRESH_COOKIE: &str = "refresh_token";
+
+/// How the caller proved who they are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// The `auth_token` cookie of the web UI
+    Session,
+    /// An API token sent as `Authorization: Bearer`
+    ApiToken,
+}
 
+/// The authenticated caller, built from a verified token and the user's current row
 #[derive(Debug, Clone)]
-pub struct UserId(String);
+pub struct Principal {
+    pub user_id: i64,
+    pub role: String,
+    /// `sid` of the login session, API tokens have none
+    pub session: Option<String>,
+    pub kind: TokenKind,
+}
+
+impl Principal {
+    pub fn is_admin(&self) -> bool {
+        self.role == ROLE_ADMIN
+    }
 
-impl UserId {
-    pub fn into_i64(self) -> i64 {
-        self.0.parse().unwrap()
+    /// Viewers can look at links but not create or change them
+    pub fn can_edit(&self) -> bool {
+        self.role != ROLE_VIEWER
     }
 }
 
+/// Takes the principal `auth_cookie_middleware` found, or authenticates the request itself on routes without it
+/// Use `Option<Principal>` on pages that work for anonymous visitors too
+#[async_trait]
+impl<S> FromRequestParts<S> for Principal
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(principal) = parts.extensions.get::<Principal>() {
+            return Ok(principal.clone());
+        }
+
+        let config = Arc::<Config>::from_ref(state);
+        let principal = check_auth(&parts.headers, &config)
+            .await
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        parts.extensions.insert(principal.clone());
+        Ok(principal)
+    }
+}
+
+/// Reject requests that aren't authenticated, renewing an access token that is about to expire first
 pub async fn auth_cookie_middleware(
-    headers: HeaderMap,
+    State(config): State<Arc<Config>>,
     mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    if let Some(user_id) = check_auth(&headers).await {
-        req.extensions_mut().insert(user_id);
-        Ok(next.run(req).await)
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
+    let cookies = refresh_session(&mut req, &config).await;
+    let principal = check_auth(req.headers(), &config)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    req.extensions_mut().insert(principal);
+
+    let mut response = next.run(req).await;
+    response
+        .headers_mut()
+        .extend(cookies.into_iter().map(|cookie| (SET_COOKIE, cookie)));
+    Ok(response)
+}
+
+/// Renew an access token that is about to expire, for pages that anonymous visitors can see too
+pub async fn session_refresh_middleware(
+    State(config): State<Arc<Config>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let cookies = refresh_session(&mut req, &config).await;
+    let mut response = next.run(req).await;
+    if !cookies.is_empty() {
+        // whatever the route allows, a response carrying fresh tokens is for this browser alone
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
     }
+    response
+        .headers_mut()
+        .extend(cookies.into_iter().map(|cookie| (SET_COOKIE, cookie)));
+    response
 }
 
-pub async fn check_auth(headers: &HeaderMap) -> Option<UserId> {
-    let key = Key::from(env::get_cookie_encryption_key().as_bytes());
-    let jar = PrivateCookieJar::from_headers(headers, key);
+/// Only let admins through, has to run after `auth_cookie_middleware`
+pub async fn admin_middleware(
+    Extension(principal): Extension<Principal>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match principal.is_admin() {
+        true => Ok(next.run(req).await),
+        false => Err(StatusCode::FORBIDDEN),
+    }
+}
 
-    match jar.get("auth_token") {
-        Some(auth_token) => {
-            let jwt_secret = DecodingKey::from_ed_pem(&env::get_jwt_public())
-                .expect("Failed to create JWT public key");
-            let decoded_token = decode::<JWT>(
-                auth_token.value(),
-                &jwt_secret,
-                &Validation::new(Algorithm::EdDSA),
-            );
-            if decoded_token.is_err() {
-                trace!("Failed to decode JWT: {:?}", decoded_token);
-                return None;
-            }
+/// Only let viewers read, has to run after `auth_cookie_middleware`
+pub async fn editor_middleware(
+    Extension(principal): Extension<Principal>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match req.method().is_safe() || principal.can_edit() {
+        true => Ok(next.run(req).await),
+        false => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+pub async fn check_auth(headers: &HeaderMap, config: &Config) -> Option<Principal> {
+    let principal = authenticate(headers, config).await;
+    if let Some(principal) = &principal {
+        // tag the access log line and everything else logged for this request
+        tracing::Span::current().record("user_id", principal.user_id);
+    }
+    principal
+}
 
-            if let Some(user_id) = jar.get("user_id") {
-                return Some(UserId(user_id.value().to_string()));
-            } else {
+async fn authenticate(headers: &HeaderMap, config: &Config) -> Option<Principal> {
+    // API clients send a token instead of the cookies of the web UI
+    let (user_id, session, kind) = match get_bearer_token(headers) {
+        Some(token) => {
+            let user_id = match timed(
+                "get_user_id_by_token",
+                models::token::get_user_id_by_token(token),
+            )
+            .await
+            {
+                Ok(user_id) => user_id?,
+                Err(err) => {
+                    error!("Failed to check API token: {:?}", err);
+                    return None;
+                }
+            };
+            (user_id, None, TokenKind::ApiToken)
+        }
+        None => {
+            // a valid signature isn't enough, the session must not have been revoked since
+            let claims = verified_claims(headers, config)?;
+            let session_user_id = match timed(
+                "get_session_user_id",
+                models::session::get_session_user_id(&claims.sid),
+            )
+            .await
+            {
+                Ok(user_id) => user_id?,
+                Err(err) => {
+                    error!("Failed to check session: {:?}", err);
+                    return None;
+                }
+            };
+            if session_user_id != claims.user_id {
+                warn!(
+                    "Session {} belongs to user {}, not to user {} of the token",
+                    claims.sid, session_user_id, claims.user_id
+                );
                 return None;
             }
+            (claims.user_id, Some(claims.sid), TokenKind::Session)
         }
-        None => {
+    };
+
+    // the role can change and the user can be disabled after the token was issued
+    let user = match timed("get_user_by_id", models::user::get_user_by_id(user_id)).await {
+        Ok(user) => user,
+        Err(err) => {
+            trace!("Failed to get user {} of a valid token: {:?}", user_id, err);
             return None;
         }
+    };
+    if user.disabled {
+        trace!("User {} is disabled", user_id);
+        return None;
     }
+
+    Some(Principal {
+        user_id,
+        role: user.role,
+        session,
+        kind,
+    })
+}
+
+/// The claims of the login cookie, if it carries an unexpired token we signed
+pub fn verified_claims(headers: &HeaderMap, config: &Config) -> Option<JWT> {
+    decode_auth_cookie(headers, config, true)
+}
+
+/// The claims of the login cookie even when it expired, enough to tell which session it names
+pub fn signed_claims(headers: &HeaderMap, config: &Config) -> Option<JWT> {
+    decode_auth_cookie(headers, config, false)
+}
+
+fn decode_auth_cookie(headers: &HeaderMap, config: &Config, validate_exp: bool) -> Option<JWT> {
+    let jar = PrivateCookieJar::from_headers(headers, config.cookie_key.clone());
+    let auth_token = jar.get(AUTH_COOKIE)?;
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.validate_exp = validate_exp;
+    match decode::<JWT>(auth_token.value(), &config.jwt.decoding, &validation) {
+        Ok(token) => Some(token.claims),
+        Err(err) => {
+            trace!("Failed to decode JWT: {:?}", err);
+            None
+        }
+    }
+}
+
+/// The `sid` of the refresh cookie, for when the access token is gone
+pub fn refresh_cookie_sid(headers: &HeaderMap, config: &Config) -> Option<String> {
+    let jar = PrivateCookieJar::from_headers(headers, config.cookie_key.clone());
+    let refresh_token = jar.get(REFRESH_COOKIE)?;
+    refresh_token_sid(refresh_token.value()).map(|sid| sid.to_string())
+}
+
+/// The cookies a login hands to the browser, encrypted and ready for `Set-Cookie`
+/// Without a refresh token only the access token is renewed and the browser keeps its refresh cookie
+pub fn session_cookies(
+    access_token: String,
+    refresh_token: Option<String>,
+    config: &Config,
+) -> Vec<Cookie<'static>> {
+    let mut jar = CookieJar::new();
+    let mut private = jar.private_mut(&config.cookie_key);
+    private.add(
+        Cookie::build((AUTH_COOKIE, access_token))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax),
+    );
+    if let Some(refresh_token) = refresh_token {
+        private.add(
+            Cookie::build((REFRESH_COOKIE, refresh_token))
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(time::Duration::seconds(
+                    config.session.refresh_token_ttl.as_secs() as i64,
+                )),
+        );
+    }
+
+    jar.delta().cloned().collect()
+}
+
+/// Swap the refresh cookie for a new access token once the current one has less than a quarter of its life left
+/// The request is rewritten to carry the new cookies so everything after this sees the renewed login,
+/// the returned `Set-Cookie` values hand them to the browser
+async fn refresh_session(req: &mut Request, config: &Config) -> Vec<HeaderValue> {
+    if get_bearer_token(req.headers()).is_some() {
+        return Vec::new();
+    }
+
+    let threshold = config.session.access_token_ttl / 4;
+    let expires_soon = match signed_claims(req.headers(), config) {
+        Some(claims) => {
+            let remaining = (claims.exp as i64 - Utc::now().timestamp()).max(0) as u64;
+            Duration::from_secs(remaining) < threshold
+        }
+        None => true,
+    };
+    if !expires_soon {
+        return Vec::new();
+    }
+
+    let jar = PrivateCookieJar::from_headers(req.headers(), config.cookie_key.clone());
+    let refresh_token = match jar.get(REFRESH_COOKIE) {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Vec::new(),
+    };
+    let sid = match refresh_token_sid(&refresh_token) {
+        Some(sid) => sid.to_string(),
+        None => return Vec::new(),
+    };
+
+    let rotation = timed(
+        "rotate_refresh_token",
+        models::session::rotate_refresh_token(&refresh_token, config.session.refresh_token_ttl),
+    )
+    .await;
+    let (user_id, refresh_token) = match rotation {
+        Ok(Rotation::Rotated {
+            user_id,
+            refresh_token,
+        }) => (user_id, Some(refresh_token)),
+        Ok(Rotation::Concurrent { user_id }) => (user_id, None),
+        Ok(Rotation::Reused) => {
+            warn!(
+                "Refresh token of session {} was used again after rotation, revoked the session",
+                sid
+            );
+            return Vec::new();
+        }
+        Ok(Rotation::Invalid) => {
+            trace!("Refresh token of session {} is no longer valid", sid);
+            return Vec::new();
+        }
+        Err(err) => {
+            error!("Failed to rotate refresh token: {:?}", err);
+            return Vec::new();
+        }
+    };
+
+    let access_token = match jwt::create_jwt(
+        user_id,
+        &sid,
+        config.session.access_token_ttl,
+        &config.jwt.encoding,
+    ) {
+        Ok(token) => token,
+        Err(err) => {
+            error!("Failed to create JWT: {:?}", err);
+            return Vec::new();
+        }
+    };
+    debug!("Refreshed the access token of session {}", sid);
+
+    let cookies = session_cookies(access_token, refresh_token, config);
+    replace_request_cookies(req.headers_mut(), &cookies);
+    cookies
+        .iter()
+        .filter_map(|cookie| HeaderValue::from_str(&cookie.encoded().to_string()).ok())
+        .collect()
+}
+
+/// Put `cookies` into the `Cookie` header of a request in place of the ones with the same names
+fn replace_request_cookies(headers: &mut HeaderMap, cookies: &[Cookie<'static>]) {
+    let mut pairs = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(|pair| pair.trim().to_string())
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && !cookies.iter().any(|cookie| cookie.name() == name)
+        })
+        .collect::<Vec<_>>();
+    pairs.extend(
+        cookies
+            .iter()
+            .map(|cookie| cookie.stripped().encoded().to_string()),
+    );
+
+    if let Ok(value) = HeaderValue::from_str(&pairs.join("; ")) {
+        headers.insert(COOKIE, value);
+    }
+}
+
+fn get_bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim())
 }
 
 pub fn build_expired_cookie(name: &str) -> Cookie {
@@ -1,36 +1,137 @@
-use axum::{extract::Request, http::Uri, middleware::Next, response::Response};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 
-#[derive(Debug, Clone)]
-pub struct RequestUri(Uri);
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{header::HOST, Uri},
+    middleware::Next,
+    response::Response,
+};
+use tower_http::request_id::RequestId;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-pub async fn uri_middleware(request: Request, next: Next) -> Response {
-    let uri = request.uri().clone();
+use crate::{config::Config, telemetry, utils::client_ip};
 
-    let mut response = next.run(request).await;
+/// Query parameters whose values must never end up in the logs, like the OAuth authorization code
+const REDACTED_PARAMS: [&str; 1] = ["code"];
 
-    response.extensions_mut().insert(RequestUri(uri));
+/// Run the request inside a span carrying its request id, then write one access log line for it
+/// `user_id` starts empty and is recorded by `check_auth` once it knows who is asking
+pub async fn access_log_middleware(
+    State(config): State<Arc<Config>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = redact_query(request.uri());
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let client_ip = client_ip::get_client_ip(request.headers(), peer.ip(), &config.trusted_proxies);
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "request",
+        request_id,
+        user_id = tracing::field::Empty,
+        otel.name = format!("{} {}", method, route),
+        otel.kind = "server",
+        otel.status_code = tracing::field::Empty,
+        http.request.method = %method,
+        http.route = route,
+        http.response.status_code = tracing::field::Empty,
+    );
+    span.set_parent(telemetry::remote_context(request.headers()));
+    let response = next.run(request).instrument(span.clone()).await;
+
+    let _span = span.enter();
+    let status = response.status().as_u16();
+    span.record("http.response.status_code", status);
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    if matches!(path.as_str(), "/healthcheck" | "/livez" | "/readyz") {
+        tracing::trace!(target: "shidou::access", %method, path, status, latency_ms, host, %client_ip, "request");
+    } else {
+        tracing::info!(target: "shidou::access", %method, path, status, latency_ms, host, %client_ip, "request");
+    }
 
     response
 }
 
-pub fn logging_middleware(
-    response: &Response,
-    latency: std::time::Duration,
-    _span: &tracing::Span,
-) {
-    let url = match response.extensions().get::<RequestUri>().map(|r| &r.0) {
-        Some(uri) => uri.to_string(),
-        None => "unknown".to_string(),
+/// Path and query of the request with the values of `REDACTED_PARAMS` blanked out
+fn redact_query(uri: &Uri) -> String {
+    let query = match uri.query() {
+        Some(query) => query,
+        None => return uri.path().to_string(),
     };
-    let status = response.status();
-    // TODO add this function impl
-    // let latency = utils::duration_to_ms_string(latency);
-    let latency = format!("{:?}", latency);
-
-    if url == "/healthcheck" {
-        tracing::trace!("{} {} {}", url, status, latency);
-        return;
+
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if REDACTED_PARAMS.contains(&name) => format!("{}=REDACTED", name),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    format!("{}?{}", uri.path(), query)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use super::redact_query;
+
+    fn redact(uri: &'static str) -> String {
+        redact_query(&Uri::from_static(uri))
+    }
+
+    #[test]
+    fn redacts_the_authorization_code() {
+        assert_eq!(
+            redact("/auth/callback?code=abc123&state=xyz"),
+            "/auth/callback?code=REDACTED&state=xyz"
+        );
+        assert_eq!(
+            redact("/auth/callback?state=xyz&code=abc123"),
+            "/auth/callback?state=xyz&code=REDACTED"
+        );
+    }
+
+    #[test]
+    fn keeps_a_bare_code_without_value() {
+        assert_eq!(
+            redact("/auth/callback?code&state=xyz"),
+            "/auth/callback?code&state=xyz"
+        );
     }
 
-    tracing::debug!("{} {} {}", url, status, latency);
+    #[test]
+    fn leaves_other_params_alone() {
+        assert_eq!(
+            redact("/abc?preview&zipcode=1234"),
+            "/abc?preview&zipcode=1234"
+        );
+        assert_eq!(redact("/abc"), "/abc");
+    }
 }
@@ -1,2 +1,5 @@
 pub mod auth;
 pub mod logging;
+pub mod metrics;
+pub mod public_url;
+pub mod rate_limit;
@@ -0,0 +1,88 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::HOST, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    config::{BaseUrl, Config},
+    utils::{client_ip, forwarded},
+};
+
+/// The public address the client used to reach us, resolved once per request
+#[derive(Debug, Clone)]
+pub struct PublicUrl {
+    /// Scheme and host without a trailing slash, e.g. `https://shidou.example.com`
+    pub base: String,
+    /// Host and port, as stored in `redirect_host`
+    pub host: String,
+}
+
+impl From<&BaseUrl> for PublicUrl {
+    fn from(base_url: &BaseUrl) -> Self {
+        PublicUrl {
+            base: base_url.origin.clone(),
+            host: base_url.host.clone(),
+        }
+    }
+}
+
+pub async fn public_url_middleware(
+    State(config): State<Arc<Config>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let public_url = resolve(req.headers(), peer, &config);
+
+    req.extensions_mut().insert(public_url);
+    next.run(req).await
+}
+
+/// A configured base url always wins, so a spoofed Host header can't end up in links or the OAuth redirect
+/// Without any base urls the request itself is trusted, taking forwarded headers from trusted proxies into account
+fn resolve(headers: &HeaderMap, peer: Option<std::net::IpAddr>, config: &Config) -> PublicUrl {
+    let from_proxy = peer.is_some_and(|ip| client_ip::is_trusted(&ip, &config.trusted_proxies));
+    let forwarded = match from_proxy {
+        true => forwarded::parse(headers),
+        false => forwarded::Forwarded::default(),
+    };
+    let host = forwarded.host.or_else(|| {
+        headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(|host| host.to_string())
+    });
+
+    if let Some(host) = &host {
+        let matching = config
+            .base_urls
+            .iter()
+            .find(|base_url| base_url.host.eq_ignore_ascii_case(host));
+        if let Some(base_url) = matching {
+            return base_url.into();
+        }
+    }
+    if let Some(base_url) = config.base_urls.first() {
+        return base_url.into();
+    }
+
+    let host = host.unwrap_or_else(|| format!("localhost:{}", config.port));
+    let proto = forwarded.proto.unwrap_or_else(|| {
+        match host.starts_with("localhost") || host.starts_with("127.0.0.1") {
+            true => "http".to_string(),
+            false => "https".to_string(),
+        }
+    });
+
+    PublicUrl {
+        base: format!("{}://{}", proto, host),
+        host,
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+use serde_json::json;
+
+use crate::{config::RateLimit, middleware::auth::Principal, utils::client_ip};
+
+/// How often buckets that have filled back up are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+/// Token buckets for one group of routes, keyed by user when authenticated and by client IP otherwise
+pub struct RateLimiter {
+    /// Name of the group, used as the `policy` label of `shidou_rate_limited_total`
+    name: &'static str,
+    limit: RateLimit,
+    trusted_proxies: Vec<IpNet>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, limit: RateLimit, trusted_proxies: &[IpNet]) -> Arc<Self> {
+        Arc::new(RateLimiter {
+            name,
+            limit,
+            trusted_proxies: trusted_proxies.to_vec(),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        })
+    }
+
+    /// Tokens regained per second
+    fn rate(&self) -> f64 {
+        self.limit.requests as f64 / self.limit.period.as_secs_f64()
+    }
+
+    /// Take a token from the bucket of `key`, or say how long until the next one is available
+    fn acquire(&self, key: String) -> Result<(), Duration> {
+        self.acquire_at(key, Instant::now())
+    }
+
+    fn acquire_at(&self, key: String, now: Instant) -> Result<(), Duration> {
+        let capacity = self.limit.requests as f64;
+        let rate = self.rate();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+
+        if now.duration_since(buckets.pruned) > PRUNE_INTERVAL {
+            // a bucket that is full again behaves exactly like a missing one
+            buckets.buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+            buckets.pruned = now;
+        }
+
+        let bucket = buckets.buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Answer 429 with `Retry-After` once the caller used up its bucket
+/// Runs after `auth_cookie_middleware` on authenticated routes, so users behind one IP don't share a limit
+pub async fn rate_limit_middleware(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = match request.extensions().get::<Principal>() {
+        Some(principal) => format!("user:{}", principal.user_id),
+        None => {
+            let ip =
+                client_ip::get_client_ip(request.headers(), peer.ip(), &limiter.trusted_proxies);
+            format!("ip:{}", ip)
+        }
+    };
+
+    match limiter.acquire(key) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            metrics::counter!("shidou_rate_limited_total", "policy" => limiter.name).increment(1);
+            tracing::debug!("Rate limited by the {} policy", limiter.name);
+            let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+                Json(json!({ "error": "Too many requests, try again later" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+    use crate::config::RateLimit;
+
+    fn limiter(requests: u32, period: Duration) -> std::sync::Arc<RateLimiter> {
+        RateLimiter::new("test", RateLimit { requests, period }, &[])
+    }
+
+    #[test]
+    fn drains_then_refuses() {
+        let limiter = limiter(3, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.acquire_at("ip:1".to_string(), now).is_ok());
+        }
+        assert!(limiter.acquire_at("ip:1".to_string(), now).is_err());
+        // other callers have buckets of their own
+        assert!(limiter.acquire_at("ip:2".to_string(), now).is_ok());
+    }
+
+    #[test]
+    fn retry_after_is_the_time_to_the_next_token() {
+        // a token every 20 seconds
+        let limiter = limiter(3, Duration::from_secs(60));
+        let now = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire_at("ip:1".to_string(), now).unwrap();
+        }
+
+        let retry_after = limiter.acquire_at("ip:1".to_string(), now).unwrap_err();
+        assert_eq!(retry_after.as_secs_f64().round(), 20.0);
+
+        let retry_after = limiter
+            .acquire_at("ip:1".to_string(), now + Duration::from_secs(15))
+            .unwrap_err();
+        assert_eq!(retry_after.as_secs_f64().round(), 5.0);
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = limiter(3, Duration::from_secs(60));
+        let now = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire_at("ip:1".to_string(), now).unwrap();
+        }
+
+        let later = now + Duration::from_secs(20);
+        assert!(limiter.acquire_at("ip:1".to_string(), later).is_ok());
+        assert!(limiter.acquire_at("ip:1".to_string(), later).is_err());
+
+        // never more than a full bucket, however long the caller was away
+        let much_later = now + Duration::from_secs(60 * 60);
+        for _ in 0..3 {
+            assert!(limiter.acquire_at("ip:1".to_string(), much_later).is_ok());
+        }
+        assert!(limiter.acquire_at("ip:1".to_string(), much_later).is_err());
+    }
+}
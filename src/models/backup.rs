@@ -0,0 +1,418 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use chrono::Utc;
+use futures_util::{stream, Stream};
+use libsql::Value;
+use serde_json::{json, Map};
+
+use crate::{database::get_conn, models::identity};
+
+/// Bumped whenever a change to the tables would break restoring older exports
+/// 2 added `identities`
+pub const SCHEMA_VERSION: u32 = 2;
+/// First version whose exports have the `identities` table
+const IDENTITIES_SCHEMA_VERSION: u64 = 2;
+
+/// Every table in the export, ordered so rows are restored after the rows they reference
+const TABLES: [&str; 7] = [
+    "users",
+    "identities",
+    "redirects",
+    "redirect_rules",
+    "redirect_locales",
+    "redirect_targets",
+    "visits",
+];
+
+/// First field of the CSV records that start the export and each table
+const CSV_EXPORT_MARKER: &str = "#shidou-export";
+const CSV_TABLE_MARKER: &str = "#table";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupFormat {
+    /// One JSON object per line, a header followed by `{ "table": ..., "row": {...} }` lines
+    #[default]
+    Jsonl,
+    /// A header record, then per table a `#table,<name>` record, the column names and the rows
+    Csv,
+}
+
+impl BackupFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            BackupFormat::Jsonl => "application/x-ndjson",
+            BackupFormat::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BackupFormat::Jsonl => "jsonl",
+            BackupFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for BackupFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" => Ok(BackupFormat::Jsonl),
+            "csv" => Ok(BackupFormat::Csv),
+            _ => Err(format!("Unsupported backup format: {}", s)),
+        }
+    }
+}
+
+fn csv_line(fields: &[String]) -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(fields)?;
+    let bytes = writer.into_inner().context("Failed to write CSV record")?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn value_to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => json!(i),
+        Value::Real(f) => json!(f),
+        Value::Text(s) => json!(s),
+        Value::Blob(b) => json!(b),
+    }
+}
+
+fn value_to_csv(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        Value::Text(s) => s,
+        Value::Blob(b) => b.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+fn header_line(format: BackupFormat) -> anyhow::Result<String> {
+    let exported_utc = Utc::now().to_rfc3339();
+    match format {
+        BackupFormat::Jsonl => Ok(format!(
+            "{}\n",
+            json!({ "shidou_export": true, "schema_version": SCHEMA_VERSION, "exported_utc": exported_utc })
+        )),
+        BackupFormat::Csv => csv_line(&[
+            CSV_EXPORT_MARKER.to_string(),
+            SCHEMA_VERSION.to_string(),
+            exported_utc,
+        ]),
+    }
+}
+
+fn row_line(
+    format: BackupFormat,
+    table: &str,
+    columns: &[String],
+    row: &libsql::Row,
+) -> anyhow::Result<String> {
+    let mut values = vec![];
+    for i in 0..columns.len() {
+        values.push(row.get_value(i as i32)?);
+    }
+
+    match format {
+        BackupFormat::Jsonl => {
+            let row: Map<String, serde_json::Value> = columns
+                .iter()
+                .cloned()
+                .zip(values.into_iter().map(value_to_json))
+                .collect();
+            Ok(format!("{}\n", json!({ "table": table, "row": row })))
+        }
+        BackupFormat::Csv => csv_line(&values.into_iter().map(value_to_csv).collect::<Vec<_>>()),
+    }
+}
+
+enum ExportState {
+    Header,
+    Table(usize),
+    Rows(usize, Vec<String>, libsql::Rows),
+    Done,
+}
+
+/// Stream every table of the database, one line at a time
+pub async fn export_stream(
+    format: BackupFormat,
+) -> impl Stream<Item = anyhow::Result<String>> + Send + 'static {
+    let conn = get_conn().await;
+
+    stream::unfold(ExportState::Header, move |state| {
+        let conn = conn.clone();
+        async move {
+            match state {
+                ExportState::Header => Some((header_line(format), ExportState::Table(0))),
+                ExportState::Table(i) if i >= TABLES.len() => None,
+                ExportState::Table(i) => {
+                    let table = TABLES[i];
+                    let rows = match conn
+                        .query(&format!("SELECT * FROM {}", table), libsql::params!())
+                        .await
+                    {
+                        Ok(rows) => rows,
+                        Err(err) => {
+                            let err = anyhow::anyhow!("Failed to export {} table: {}", table, err);
+                            return Some((Err(err), ExportState::Done));
+                        }
+                    };
+                    let columns: Vec<String> = (0..rows.column_count())
+                        .map(|c| rows.column_name(c).unwrap_or_default().to_string())
+                        .collect();
+
+                    // JSON lines carry their table and columns, CSV needs them up front
+                    let chunk = match format {
+                        BackupFormat::Jsonl => Ok(String::new()),
+                        BackupFormat::Csv => {
+                            csv_line(&[CSV_TABLE_MARKER.to_string(), table.to_string()])
+                                .and_then(|marker| Ok(marker + &csv_line(&columns)?))
+                        }
+                    };
+                    Some((chunk, ExportState::Rows(i, columns, rows)))
+                }
+                ExportState::Rows(i, columns, mut rows) => match rows.next().await {
+                    Ok(Some(row)) => {
+                        let line = row_line(format, TABLES[i], &columns, &row);
+                        Some((line, ExportState::Rows(i, columns, rows)))
+                    }
+                    Ok(None) => Some((Ok(String::new()), ExportState::Table(i + 1))),
+                    Err(err) => {
+                        let err = anyhow::anyhow!("Failed to export {} table: {}", TABLES[i], err);
+                        Some((Err(err), ExportState::Done))
+                    }
+                },
+                ExportState::Done => None,
+            }
+        }
+    })
+}
+
+/// An export read back, before anything is written
+struct ParsedExport {
+    schema_version: u64,
+    tables: Vec<TableRows>,
+}
+
+/// Rows of one table read back from an export
+struct TableRows {
+    table: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+fn json_to_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::Text(s),
+        other => Value::Text(other.to_string()),
+    }
+}
+
+fn check_schema_version(version: u64) -> anyhow::Result<u64> {
+    if version > SCHEMA_VERSION as u64 {
+        anyhow::bail!(
+            "Export has schema version {}, this build of shidou only understands up to {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(version)
+}
+
+fn push_row(tables: &mut Vec<TableRows>, table: &str, columns: Vec<String>, row: Vec<Value>) {
+    match tables
+        .iter_mut()
+        .find(|t| t.table == table && t.columns == columns)
+    {
+        Some(t) => t.rows.push(row),
+        None => tables.push(TableRows {
+            table: table.to_string(),
+            columns,
+            rows: vec![row],
+        }),
+    }
+}
+
+fn parse_jsonl(input: &str) -> anyhow::Result<ParsedExport> {
+    let mut lines = input.lines().filter(|line| !line.trim().is_empty());
+
+    let header: serde_json::Value = serde_json::from_str(lines.next().context("Export is empty")?)
+        .context("Failed to parse export header")?;
+    if header["shidou_export"] != json!(true) {
+        anyhow::bail!("Not a shidou export");
+    }
+    let schema_version =
+        check_schema_version(header["schema_version"].as_u64().unwrap_or_default())?;
+
+    let mut tables: Vec<TableRows> = vec![];
+    for (i, line) in lines.enumerate() {
+        let mut line: serde_json::Value = serde_json::from_str(line)
+            .with_context(|| format!("Failed to parse line {} of export", i + 2))?;
+        let table = line["table"]
+            .as_str()
+            .with_context(|| format!("Missing table on line {} of export", i + 2))?
+            .to_string();
+        let row = match line["row"].take() {
+            serde_json::Value::Object(row) => row,
+            _ => anyhow::bail!("Missing row on line {} of export", i + 2),
+        };
+
+        let (columns, values): (Vec<String>, Vec<Value>) =
+            row.into_iter().map(|(c, v)| (c, json_to_value(v))).unzip();
+        push_row(&mut tables, &table, columns, values);
+    }
+
+    Ok(ParsedExport {
+        schema_version,
+        tables,
+    })
+}
+
+fn parse_csv(input: &str) -> anyhow::Result<ParsedExport> {
+    let mut records = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input.as_bytes())
+        .into_records();
+
+    let header = records
+        .next()
+        .context("Export is empty")?
+        .context("Failed to parse export header")?;
+    if header.get(0) != Some(CSV_EXPORT_MARKER) {
+        anyhow::bail!("Not a shidou export");
+    }
+    let schema_version = check_schema_version(
+        header
+            .get(1)
+            .unwrap_or_default()
+            .parse()
+            .unwrap_or_default(),
+    )?;
+
+    let mut tables: Vec<TableRows> = vec![];
+    while let Some(record) = records.next() {
+        let record = record.context("Failed to read export")?;
+        if record.get(0) == Some(CSV_TABLE_MARKER) {
+            let table = record.get(1).context("Missing table name")?.to_string();
+            let columns = records
+                .next()
+                .with_context(|| format!("Missing columns of {} table", table))?
+                .context("Failed to read export")?
+                .iter()
+                .map(|c| c.to_string())
+                .collect();
+            tables.push(TableRows {
+                table,
+                columns,
+                rows: vec![],
+            });
+            continue;
+        }
+
+        let table = tables.last_mut().context("Row outside of a table")?;
+        // CSV has no NULL, empty fields are restored as NULL
+        let row = record
+            .iter()
+            .map(|field| match field {
+                "" => Value::Null,
+                field => Value::Text(field.to_string()),
+            })
+            .collect();
+        table.rows.push(row);
+    }
+
+    Ok(ParsedExport {
+        schema_version,
+        tables,
+    })
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RestoreReport {
+    pub tables: Vec<(String, usize)>,
+}
+
+/// Restore an export into an empty database, in a single transaction
+/// Exports from before `identities` get their Discord users linked the way startup links existing ones
+pub async fn restore(input: &str, format: BackupFormat) -> anyhow::Result<RestoreReport> {
+    let ParsedExport {
+        schema_version,
+        tables,
+    } = match format {
+        BackupFormat::Jsonl => parse_jsonl(input)?,
+        BackupFormat::Csv => parse_csv(input)?,
+    };
+
+    if let Some(unknown) = tables.iter().find(|t| !TABLES.contains(&t.table.as_str())) {
+        anyhow::bail!("Export contains unknown table {}", unknown.table);
+    }
+
+    let conn = get_conn().await;
+    let tx = conn
+        .transaction_with_behavior(libsql::TransactionBehavior::Immediate)
+        .await
+        .context("Failed to start transaction")?;
+
+    for table in TABLES {
+        let mut rows = tx
+            .query(
+                &format!("SELECT 1 FROM {} LIMIT 1", table),
+                libsql::params!(),
+            )
+            .await
+            .with_context(|| format!("Failed to check {} table", table))?;
+        if let Ok(Some(_)) = rows.next().await {
+            anyhow::bail!("Restoring needs an empty database, {} has rows", table);
+        }
+    }
+
+    let mut report = RestoreReport::default();
+    // restore in dependency order, whatever order the export was written in
+    for name in TABLES {
+        let mut count = 0;
+        for table in tables.iter().filter(|t| t.table == name) {
+            // column names can't be bound as parameters, so only allow plain identifiers
+            if let Some(column) = table.columns.iter().find(|c| {
+                c.is_empty() || !c.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+            }) {
+                anyhow::bail!("Invalid column name in {} table: {:?}", name, column);
+            }
+
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                name,
+                table.columns.join(", "),
+                vec!["?"; table.columns.len()].join(", ")
+            );
+            for row in &table.rows {
+                tx.execute(&sql, row.clone())
+                    .await
+                    .with_context(|| format!("Failed to restore row into {} table", name))?;
+                count += 1;
+            }
+        }
+        report.tables.push((name.to_string(), count));
+    }
+
+    if schema_version < IDENTITIES_SCHEMA_VERSION {
+        identity::link_discord_users(&tx).await?;
+    }
+
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    Ok(report)
+}
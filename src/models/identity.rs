@@ -0,0 +1,124 @@
+use anyhow::Context;
+use libsql::named_params;
+
+use crate::{database::get_conn, utils::oauth::Identity};
+
+use super::user::{self, UserRow};
+
+/// Discord accounts also live in `users.discord_snowflake`, which the CLI and older exports still use
+const DISCORD: &str = "discord";
+
+/// The user an account at a login provider belongs to, creating one on its first login
+/// A role the provider decided on replaces the one stored for the user
+pub async fn upsert_identity_user(provider: &str, identity: &Identity) -> anyhow::Result<UserRow> {
+    let user = find_or_create_user(provider, identity).await?;
+
+    match identity.role {
+        Some(role) if role != user.role => user::set_user_role(user.id, role).await,
+        _ => Ok(user),
+    }
+}
+
+/// `users.discord_username` doubles as the display name of users without a Discord account
+async fn find_or_create_user(provider: &str, identity: &Identity) -> anyhow::Result<UserRow> {
+    if provider == DISCORD {
+        let user = user::upsert_user(&identity.subject, &identity.username).await?;
+        link_identity(user.id, provider, identity).await?;
+        return Ok(user);
+    }
+
+    if let Some(user_id) = get_user_id_by_identity(provider, &identity.subject).await? {
+        link_identity(user_id, provider, identity).await?;
+        return user::get_user_by_id(user_id).await;
+    }
+
+    let conn = get_conn().await;
+    let tx = conn
+        .transaction()
+        .await
+        .context("Failed to start transaction")?;
+
+    tx.execute(
+        "INSERT INTO users (discord_username) VALUES (:username)",
+        named_params!(
+            ":username": identity.username.as_str(),
+        ),
+    )
+    .await
+    .context("Failed to insert user into database")?;
+    let user_id = tx.last_insert_rowid();
+
+    tx.execute(
+        "INSERT INTO identities (user_id, provider, subject, username)
+        VALUES (:user_id, :provider, :subject, :username)",
+        named_params!(
+            ":user_id": user_id,
+            ":provider": provider,
+            ":subject": identity.subject.as_str(),
+            ":username": identity.username.as_str(),
+        ),
+    )
+    .await
+    .context("Failed to insert identity into database")?;
+
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    user::get_user_by_id(user_id).await
+}
+
+/// Record a login of `identity` for a user, refreshing the username the provider knows them by
+async fn link_identity(user_id: i64, provider: &str, identity: &Identity) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    conn.execute(
+        "INSERT INTO identities (user_id, provider, subject, username)
+        VALUES (:user_id, :provider, :subject, :username)
+        ON CONFLICT(provider, subject) DO UPDATE SET
+        username = excluded.username,
+        last_login_utc = (strftime('%Y-%m-%d %H:%M:%S', 'now'))",
+        named_params!(
+            ":user_id": user_id,
+            ":provider": provider,
+            ":subject": identity.subject.as_str(),
+            ":username": identity.username.as_str(),
+        ),
+    )
+    .await
+    .context("Failed to upsert identity into database")?;
+
+    Ok(())
+}
+
+/// Link users from before identities existed to their Discord account, the only way to log in back then
+pub async fn link_discord_users(tx: &libsql::Transaction) -> anyhow::Result<()> {
+    tx.execute(
+        "INSERT OR IGNORE INTO identities (user_id, provider, subject, username)
+        SELECT id, 'discord', discord_snowflake, discord_username FROM users
+        WHERE discord_snowflake IS NOT NULL",
+        libsql::params!(),
+    )
+    .await
+    .context("Failed to link existing users to their Discord identities")?;
+
+    Ok(())
+}
+
+pub async fn get_user_id_by_identity(provider: &str, subject: &str) -> anyhow::Result<Option<i64>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT user_id FROM identities WHERE provider = :provider AND subject = :subject",
+            named_params!(
+                ":provider": provider,
+                ":subject": subject,
+            ),
+        )
+        .await
+        .context("Failed to get identity from database")?;
+
+    match result.next().await? {
+        Some(row) => Ok(Some(row.get::<i64>(0)?)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use libsql::named_params;
+
+use crate::database::get_conn;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LocaleRow {
+    pub language: String,
+    pub url: String,
+}
+
+pub async fn get_locales(redirect_id: i64) -> anyhow::Result<Vec<LocaleRow>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT language, url FROM redirect_locales WHERE redirect_id = :redirect_id ORDER BY language",
+            named_params!(
+                ":redirect_id": redirect_id,
+            ),
+        )
+        .await
+        .context("Failed to get redirect locales from database")?;
+
+    let mut results: Vec<LocaleRow> = vec![];
+    while let Ok(Some(r)) = result.next().await {
+        let row = libsql::de::from_row::<_>(&r);
+        if let Ok(row) = row {
+            results.push(row);
+        } else {
+            tracing::error!("Failed to deserialize row: {:?}", row);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Replace the language map of a redirect, keyed by language tag
+pub async fn replace_locales(
+    redirect_id: i64,
+    locales: &BTreeMap<String, String>,
+) -> anyhow::Result<Vec<LocaleRow>> {
+    let conn = get_conn().await;
+
+    let tx = conn
+        .transaction()
+        .await
+        .context("Failed to start transaction")?;
+
+    tx.execute(
+        "DELETE FROM redirect_locales WHERE redirect_id = :redirect_id",
+        named_params!(
+            ":redirect_id": redirect_id,
+        ),
+    )
+    .await
+    .context("Failed to delete redirect locales from database")?;
+
+    for (language, url) in locales {
+        tx.execute(
+            "INSERT INTO redirect_locales (redirect_id, language, url) VALUES (:redirect_id, :language, :url)",
+            named_params!(
+                ":redirect_id": redirect_id,
+                ":language": language.to_ascii_lowercase(),
+                ":url": url.as_str(),
+            ),
+        )
+        .await
+        .context("Failed to insert redirect locale into database")?;
+    }
+
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    get_locales(redirect_id).await
+}
@@ -1,3 +1,11 @@
+pub mod backup;
 pub mod date;
+pub mod identity;
+pub mod locale;
 pub mod redirect;
+pub mod rule;
+pub mod session;
+pub mod target;
+pub mod token;
 pub mod user;
+pub mod visit;
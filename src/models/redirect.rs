@@ -1,17 +1,45 @@
-use crate::{database::get_conn, models::date::custom_date_format};
+use crate::{
+    database::{get_conn, timed},
+    models::date::custom_date_format,
+    utils::{import::ParsedRow, strings},
+};
 use anyhow::Context;
 use chrono::{DateTime, Utc};
 use libsql::named_params;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+pub use shidou::types::OpenGraph;
+
+/// How long a redirect is served from memory, edits through this instance drop it right away,
+/// edits made elsewhere (the CLI, other machines) show up once it runs out
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Redirects looked up by key with when they were, only ones that exist so a new key never waits on the cache
+type RedirectCache = Mutex<HashMap<String, (Arc<RedirectRow>, Instant)>>;
+static CACHE: OnceLock<RedirectCache> = OnceLock::new();
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[allow(dead_code)]
 pub struct RedirectRow {
-    id: i64,
+    pub id: i64,
     pub key: String,
     pub url: String,
     pub redirect_host: String,
     pub visits: u64,
     pub created_by: i64,
+    pub interstitial: bool,
+    pub og_title: Option<String>,
+    pub og_description: Option<String>,
+    pub og_image: Option<String>,
     #[serde(with = "custom_date_format")]
     pub created_utc: DateTime<Utc>,
     #[serde(with = "custom_date_format")]
@@ -23,17 +51,24 @@ pub async fn save_new_redirect(
     url: &str,
     host: &str,
     created_by: i64,
+    interstitial: bool,
+    og: &OpenGraph,
 ) -> anyhow::Result<RedirectRow> {
     let conn = get_conn().await;
 
     let result = conn
         .execute(
-            "insert into redirects (key, url, redirect_host, created_by) values (:key, :url, :redirect_host, :created_by)",
+            "insert into redirects (key, url, redirect_host, created_by, interstitial, og_title, og_description, og_image)
+            values (:key, :url, :redirect_host, :created_by, :interstitial, :og_title, :og_description, :og_image)",
             named_params!(
                 ":key": key,
                 ":url": url,
                 ":redirect_host": host,
-                ":created_by": created_by
+                ":created_by": created_by,
+                ":interstitial": interstitial,
+                ":og_title": og.og_title.as_deref(),
+                ":og_description": og.og_description.as_deref(),
+                ":og_image": og.og_image.as_deref()
             ),
         )
         .await
@@ -48,42 +83,93 @@ pub async fn save_new_redirect(
     }
 }
 
-pub async fn update_redirect(key: &str, url: &str, host: &str) -> anyhow::Result<RedirectRow> {
+/// Fields left as `None` keep their stored value, empty OpenGraph fields are cleared
+pub async fn update_redirect(
+    key: &str,
+    url: &str,
+    interstitial: Option<bool>,
+    og: &OpenGraph,
+) -> anyhow::Result<RedirectRow> {
     let conn = get_conn().await;
 
     let result = conn
         .execute(
-            "update redirects set url = :url where key = :key",
+            "update redirects set url = :url, interstitial = COALESCE(:interstitial, interstitial),
+            og_title = NULLIF(COALESCE(:og_title, og_title), ''),
+            og_description = NULLIF(COALESCE(:og_description, og_description), ''),
+            og_image = NULLIF(COALESCE(:og_image, og_image), '')
+            where key = :key",
             named_params!(
                 ":key": key,
                 ":url": url,
+                ":interstitial": interstitial,
+                ":og_title": og.og_title.as_deref(),
+                ":og_description": og.og_description.as_deref(),
+                ":og_image": og.og_image.as_deref(),
             ),
         )
         .await
         .context("Failed to update redirect in database")?;
+    forget_cached(Some(key));
 
     match result {
-        1 => Ok(get_redirect(key, host).await?),
+        // keys are unique across hosts, the host of the request may not be the one the redirect is on
+        1 => get_redirect_by_key(key)
+            .await?
+            .context("Redirect is gone after updating it"),
         _ => Err(anyhow::anyhow!("Failed to update redirect in database")),
     }
 }
 
-pub async fn delete_redirect(key: &str, host: &str) -> anyhow::Result<()> {
+/// Tables whose rows belong to a single redirect, through their `redirect_id`
+const REDIRECT_CHILD_TABLES: [&str; 4] = [
+    "redirect_rules",
+    "redirect_locales",
+    "redirect_targets",
+    "visits",
+];
+
+/// Delete a redirect along with its rules, locales, variants and visits
+/// Keys are unique across hosts, so the key alone names the redirect
+/// Foreign keys aren't enforced, so nothing cascades and a later redirect reusing the id would inherit leftover rows
+pub async fn delete_redirect(key: &str) -> anyhow::Result<()> {
     let conn = get_conn().await;
 
-    let result = conn
+    let tx = conn
+        .transaction()
+        .await
+        .context("Failed to start transaction")?;
+
+    for table in REDIRECT_CHILD_TABLES {
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE redirect_id IN (SELECT id FROM redirects WHERE key = :key)",
+                table
+            ),
+            named_params!(
+                ":key": key,
+            ),
+        )
+        .await
+        .with_context(|| format!("Failed to delete {} of redirect from database", table))?;
+    }
+
+    let result = tx
         .execute(
-            "DELETE FROM redirects WHERE key = :key AND host = :host",
+            "DELETE FROM redirects WHERE key = :key",
             named_params!(
                 ":key": key,
-                ":host": host,
             ),
         )
         .await
         .context("Failed to delete redirect from database")?;
 
     match result {
-        1 => Ok(()),
+        1 => {
+            tx.commit().await.context("Failed to commit transaction")?;
+            forget_cached(Some(key));
+            Ok(())
+        }
         _ => Err(anyhow::anyhow!("Failed to delete redirect from database")),
     }
 }
@@ -112,6 +198,81 @@ pub async fn get_redirect(key: &str, host: &str) -> anyhow::Result<RedirectRow>
     }
 }
 
+pub async fn get_redirect_by_key(key: &str) -> anyhow::Result<Option<RedirectRow>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT * FROM redirects WHERE key = :key LIMIT 1",
+            named_params!(
+                ":key": key,
+            ),
+        )
+        .await
+        .context("Failed to get redirect from database")?;
+
+    match result.next().await {
+        Ok(Some(row)) => Ok(Some(libsql::de::from_row::<_>(&row)?)),
+        Ok(None) => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Failed to get redirect by key: {}", e)),
+    }
+}
+
+fn cache() -> &'static RedirectCache {
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// `get_redirect_by_key` behind the in-memory cache, counted in `shidou_redirect_cache_total`
+pub async fn get_cached_redirect(key: &str) -> anyhow::Result<Option<Arc<RedirectRow>>> {
+    let cached = cache().lock().ok().and_then(|cache| {
+        cache
+            .get(key)
+            .filter(|(_, cached_at)| cached_at.elapsed() < CACHE_TTL)
+            .map(|(redirect, _)| redirect.clone())
+    });
+    if let Some(redirect) = cached {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+        metrics::counter!("shidou_redirect_cache_total", "result" => "hit").increment(1);
+        return Ok(Some(redirect));
+    }
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    metrics::counter!("shidou_redirect_cache_total", "result" => "miss").increment(1);
+
+    // only lookups that reach the database show up in its latency histogram
+    let redirect = timed("get_redirect_by_key", get_redirect_by_key(key))
+        .await?
+        .map(Arc::new);
+    if let (Some(redirect), Ok(mut cache)) = (&redirect, cache().lock()) {
+        // expired entries of keys nobody asks for anymore would otherwise stay forever
+        cache.retain(|_, (_, cached_at)| cached_at.elapsed() < CACHE_TTL);
+        cache.insert(key.to_string(), (redirect.clone(), Instant::now()));
+    }
+
+    Ok(redirect)
+}
+
+/// Share of cached lookups answered from memory since startup, `None` before the first one
+pub fn cache_hit_ratio() -> Option<f64> {
+    let hits = CACHE_HITS.load(Ordering::Relaxed);
+    let misses = CACHE_MISSES.load(Ordering::Relaxed);
+    match hits + misses {
+        0 => None,
+        total => Some(hits as f64 / total as f64),
+    }
+}
+
+/// Drop a redirect from the cache after it changed, `None` drops all of them
+fn forget_cached(key: Option<&str>) {
+    if let Ok(mut cache) = cache().lock() {
+        match key {
+            Some(key) => {
+                cache.remove(key);
+            }
+            None => cache.clear(),
+        }
+    }
+}
+
 pub async fn get_all_redirects() -> anyhow::Result<Vec<RedirectRow>> {
     let conn = get_conn().await;
 
@@ -152,3 +313,221 @@ pub async fn inc_visits(key: &str) -> anyhow::Result<()> {
         _ => Err(anyhow::anyhow!("Failed to increment visits in database")),
     }
 }
+
+/// What to do with an imported row whose key is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            _ => Err(format!("Unsupported conflict policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Imported,
+    Overwritten,
+    Renamed,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ImportRowReport {
+    pub row: usize,
+    pub key: Option<String>,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renamed_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Whether the rows were written, an import is only committed when no row failed
+    pub committed: bool,
+    pub imported: usize,
+    pub overwritten: usize,
+    pub renamed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRowReport>,
+}
+
+impl ImportReport {
+    fn push(&mut self, row: ImportRowReport) {
+        match row.status {
+            ImportStatus::Imported => self.imported += 1,
+            ImportStatus::Overwritten => self.overwritten += 1,
+            ImportStatus::Renamed => self.renamed += 1,
+            ImportStatus::Skipped => self.skipped += 1,
+            ImportStatus::Failed => self.failed += 1,
+        }
+        self.rows.push(row);
+    }
+}
+
+async fn key_exists(tx: &libsql::Transaction, key: &str) -> anyhow::Result<bool> {
+    let mut result = tx
+        .query(
+            "SELECT id FROM redirects WHERE key = :key LIMIT 1",
+            named_params!(
+                ":key": key,
+            ),
+        )
+        .await
+        .context("Failed to look up redirect key")?;
+
+    Ok(matches!(result.next().await, Ok(Some(_))))
+}
+
+/// Import a batch of redirects in a single transaction
+/// Nothing is written when any row fails or when `dry_run` is set, the report describes what would have happened
+pub async fn import_redirects(
+    rows: Vec<ParsedRow>,
+    default_host: &str,
+    created_by: i64,
+    policy: ConflictPolicy,
+    dry_run: bool,
+) -> anyhow::Result<ImportReport> {
+    let conn = get_conn().await;
+
+    let tx = conn
+        .transaction_with_behavior(libsql::TransactionBehavior::Immediate)
+        .await
+        .context("Failed to start transaction")?;
+
+    let mut report = ImportReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    for parsed in rows {
+        let row = match parsed.result {
+            Ok(row) => row,
+            Err(err) => {
+                report.push(ImportRowReport {
+                    row: parsed.row,
+                    key: None,
+                    status: ImportStatus::Failed,
+                    renamed_to: None,
+                    error: Some(err),
+                });
+                continue;
+            }
+        };
+        let host = row.host.as_deref().unwrap_or(default_host);
+
+        let mut key = row.key.clone();
+        let mut status = ImportStatus::Imported;
+        if key_exists(&tx, &key).await? {
+            match policy {
+                ConflictPolicy::Skip => {
+                    report.push(ImportRowReport {
+                        row: parsed.row,
+                        key: Some(row.key),
+                        status: ImportStatus::Skipped,
+                        renamed_to: None,
+                        error: None,
+                    });
+                    continue;
+                }
+                ConflictPolicy::Overwrite => status = ImportStatus::Overwritten,
+                ConflictPolicy::Rename => {
+                    let mut suffix = 2;
+                    while key_exists(&tx, &format!("{}-{}", row.key, suffix)).await? {
+                        suffix += 1;
+                    }
+                    key = format!("{}-{}", row.key, suffix);
+                    // the suffix can push a key past the length limit
+                    if let Err(err) = strings::validate_key(&key) {
+                        report.push(ImportRowReport {
+                            row: parsed.row,
+                            key: Some(row.key),
+                            status: ImportStatus::Failed,
+                            renamed_to: None,
+                            error: Some(err),
+                        });
+                        continue;
+                    }
+                    status = ImportStatus::Renamed;
+                }
+            }
+        }
+
+        let result = match status {
+            ImportStatus::Overwritten => {
+                tx.execute(
+                    "UPDATE redirects SET url = :url, redirect_host = :redirect_host,
+                    updated_utc = (strftime('%Y-%m-%d %H:%M:%S', 'now'))
+                    WHERE key = :key",
+                    named_params!(
+                        ":key": key.as_str(),
+                        ":url": row.url.as_str(),
+                        ":redirect_host": host,
+                    ),
+                )
+                .await
+            }
+            _ => {
+                tx.execute(
+                    "INSERT INTO redirects (key, url, redirect_host, created_by) VALUES (:key, :url, :redirect_host, :created_by)",
+                    named_params!(
+                        ":key": key.as_str(),
+                        ":url": row.url.as_str(),
+                        ":redirect_host": host,
+                        ":created_by": created_by,
+                    ),
+                )
+                .await
+            }
+        };
+
+        report.push(match result {
+            Ok(_) => ImportRowReport {
+                row: parsed.row,
+                renamed_to: (status == ImportStatus::Renamed).then_some(key),
+                key: Some(row.key),
+                status,
+                error: None,
+            },
+            Err(err) => ImportRowReport {
+                row: parsed.row,
+                key: Some(row.key),
+                status: ImportStatus::Failed,
+                renamed_to: None,
+                error: Some(err.to_string()),
+            },
+        });
+    }
+
+    if dry_run || report.failed > 0 {
+        tx.rollback()
+            .await
+            .context("Failed to roll back transaction")?;
+    } else {
+        tx.commit().await.context("Failed to commit transaction")?;
+        report.committed = true;
+        // overwritten rows can be anywhere in the cache
+        forget_cached(None);
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,207 @@
+use anyhow::Context;
+use libsql::named_params;
+
+use crate::{
+    database::get_conn,
+    utils::user_agent::{Device, Os, UserAgentInfo},
+};
+
+/// A conditional target for a redirect, evaluated in priority order before the default url
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct RuleRow {
+    pub id: i64,
+    pub priority: i64,
+    pub os: Option<String>,
+    pub device: Option<String>,
+    pub bot: Option<bool>,
+    pub country: Option<String>,
+    pub url: String,
+}
+
+impl RuleRow {
+    /// Every condition set on the rule has to agree with the client, unset conditions match anything
+    pub fn matches(&self, client: &UserAgentInfo, country: Option<&str>) -> bool {
+        self.os.as_ref().is_none_or(|os| os == client.os.as_str())
+            && self
+                .device
+                .as_ref()
+                .is_none_or(|device| device == client.device.as_str())
+            && self.bot.is_none_or(|bot| bot == client.bot)
+            && self
+                .country
+                .as_ref()
+                .is_none_or(|rule_country| country == Some(rule_country.as_str()))
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct RuleInput {
+    #[serde(default)]
+    pub priority: i64,
+    pub os: Option<Os>,
+    pub device: Option<Device>,
+    pub bot: Option<bool>,
+    /// ISO 3166-1 alpha-2 country code, only matched when a GeoIP database is configured
+    pub country: Option<String>,
+    pub url: String,
+}
+
+pub async fn get_rules(redirect_id: i64) -> anyhow::Result<Vec<RuleRow>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT id, priority, os, device, bot, country, url FROM redirect_rules WHERE redirect_id = :redirect_id ORDER BY priority, id",
+            named_params!(
+                ":redirect_id": redirect_id,
+            ),
+        )
+        .await
+        .context("Failed to get redirect rules from database")?;
+
+    let mut results: Vec<RuleRow> = vec![];
+    while let Ok(Some(r)) = result.next().await {
+        let row = libsql::de::from_row::<_>(&r);
+        if let Ok(row) = row {
+            results.push(row);
+        } else {
+            tracing::error!("Failed to deserialize row: {:?}", row);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Replace all rules of a redirect with the given set
+pub async fn replace_rules(redirect_id: i64, rules: &[RuleInput]) -> anyhow::Result<Vec<RuleRow>> {
+    let conn = get_conn().await;
+
+    let tx = conn
+        .transaction()
+        .await
+        .context("Failed to start transaction")?;
+
+    tx.execute(
+        "DELETE FROM redirect_rules WHERE redirect_id = :redirect_id",
+        named_params!(
+            ":redirect_id": redirect_id,
+        ),
+    )
+    .await
+    .context("Failed to delete redirect rules from database")?;
+
+    for rule in rules {
+        tx.execute(
+            "INSERT INTO redirect_rules (redirect_id, priority, os, device, bot, country, url)
+            VALUES (:redirect_id, :priority, :os, :device, :bot, :country, :url)",
+            named_params!(
+                ":redirect_id": redirect_id,
+                ":priority": rule.priority,
+                ":os": rule.os.map(|os| os.as_str()),
+                ":device": rule.device.map(|device| device.as_str()),
+                ":bot": rule.bot,
+                ":country": rule.country.as_ref().map(|country| country.to_ascii_uppercase()),
+                ":url": rule.url.as_str(),
+            ),
+        )
+        .await
+        .context("Failed to insert redirect rule into database")?;
+    }
+
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    get_rules(redirect_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuleRow;
+    use crate::utils::user_agent::parse;
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1";
+    const ANDROID_TABLET: &str = "Mozilla/5.0 (Linux; Android 13; SM-X710) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+    const WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+    const SLACKBOT: &str = "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)";
+
+    fn rule(
+        os: Option<&str>,
+        device: Option<&str>,
+        bot: Option<bool>,
+        country: Option<&str>,
+    ) -> RuleRow {
+        RuleRow {
+            id: 1,
+            priority: 0,
+            os: os.map(String::from),
+            device: device.map(String::from),
+            bot,
+            country: country.map(String::from),
+            url: "https://example.com".to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_every_set_condition() {
+        let cases = [
+            (rule(None, None, None, None), WINDOWS, None, true),
+            (rule(Some("ios"), None, None, None), IPHONE, None, true),
+            (
+                rule(Some("ios"), None, None, None),
+                ANDROID_TABLET,
+                None,
+                false,
+            ),
+            (
+                rule(Some("android"), Some("tablet"), None, None),
+                ANDROID_TABLET,
+                None,
+                true,
+            ),
+            (
+                rule(Some("android"), Some("mobile"), None, None),
+                ANDROID_TABLET,
+                None,
+                false,
+            ),
+            (
+                rule(None, Some("desktop"), Some(false), None),
+                WINDOWS,
+                None,
+                true,
+            ),
+            (rule(None, None, Some(true), None), SLACKBOT, None, true),
+            (rule(None, None, Some(false), None), SLACKBOT, None, false),
+            (
+                rule(None, None, None, Some("DE")),
+                WINDOWS,
+                Some("DE"),
+                true,
+            ),
+            (
+                rule(None, None, None, Some("DE")),
+                WINDOWS,
+                Some("FR"),
+                false,
+            ),
+            // without a GeoIP database there is no country, and country rules never match
+            (rule(None, None, None, Some("DE")), WINDOWS, None, false),
+            (
+                rule(Some("ios"), None, None, Some("DE")),
+                IPHONE,
+                Some("FR"),
+                false,
+            ),
+        ];
+
+        for (rule, user_agent, country, expected) in cases {
+            assert_eq!(
+                rule.matches(&parse(user_agent), country),
+                expected,
+                "{:?} for {} from {:?}",
+                rule,
+                user_agent,
+                country
+            );
+        }
+    }
+}
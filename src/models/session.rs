@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use libsql::named_params;
+
+use crate::{database::get_conn, utils::strings::generate_random_string};
+
+use super::{date::custom_date_format, token::hash_token};
+
+const SID_LENGTH: usize = 32;
+const REFRESH_SECRET_LENGTH: usize = 40;
+/// A refresh token replaced this recently is still honoured, so parallel requests that all
+/// refresh with the same token don't look like a stolen token being replayed
+const ROTATION_GRACE_SECONDS: i64 = 30;
+/// `last_seen_utc` is only written when it is older than this, so requests don't all turn into writes
+const LAST_SEEN_RESOLUTION_MINUTES: i64 = 5;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct SessionRow {
+    pub id: i64,
+    #[serde(skip_serializing)]
+    pub sid: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    #[serde(with = "custom_date_format")]
+    pub created_utc: DateTime<Utc>,
+    pub last_seen_utc: Option<String>,
+    pub expires_utc: String,
+}
+
+/// What came of presenting a refresh token
+pub enum Rotation {
+    /// The token was current, `refresh_token` replaces it
+    Rotated { user_id: i64, refresh_token: String },
+    /// The token was replaced a moment ago by a parallel request, it may be used for an access token once more
+    Concurrent { user_id: i64 },
+    /// An older token of the session came back, so it was copied, the whole session has been revoked
+    Reused,
+    /// No such session, or it expired or was revoked
+    Invalid,
+}
+
+/// A refresh token is `<sid>.<secret>`, so a replayed old token still leads to its session
+fn new_refresh_token(sid: &str) -> String {
+    format!("{}.{}", sid, generate_random_string(REFRESH_SECRET_LENGTH))
+}
+
+/// The `sid` a refresh token belongs to
+pub fn refresh_token_sid(refresh_token: &str) -> Option<&str> {
+    refresh_token
+        .split_once('.')
+        .map(|(sid, _)| sid)
+        .filter(|sid| !sid.is_empty())
+}
+
+/// Start a session for a user that just logged in, returning its `sid` and first refresh token
+pub async fn create_session(
+    user_id: i64,
+    lifetime: Duration,
+    user_agent: Option<&str>,
+    ip: &str,
+) -> anyhow::Result<(String, String)> {
+    let conn = get_conn().await;
+    let sid = generate_random_string(SID_LENGTH);
+    let refresh_token = new_refresh_token(&sid);
+
+    // expired sessions of the user are of no use to anyone
+    conn.execute(
+        "DELETE FROM sessions WHERE user_id = :user_id AND expires_utc <= strftime('%Y-%m-%d %H:%M:%S', 'now')",
+        named_params!(
+            ":user_id": user_id,
+        ),
+    )
+    .await
+    .context("Failed to delete expired sessions from database")?;
+
+    conn.execute(
+        "INSERT INTO sessions (sid, user_id, user_agent, ip, refresh_hash, expires_utc)
+        VALUES (:sid, :user_id, :user_agent, :ip, :refresh_hash, strftime('%Y-%m-%d %H:%M:%S', 'now', :lifetime))",
+        named_params!(
+            ":sid": sid.as_str(),
+            ":user_id": user_id,
+            ":user_agent": user_agent,
+            ":ip": ip,
+            ":refresh_hash": hash_token(&refresh_token),
+            ":lifetime": format!("+{} seconds", lifetime.as_secs()),
+        ),
+    )
+    .await
+    .context("Failed to insert session into database")?;
+
+    Ok((sid, refresh_token))
+}
+
+/// Swap a refresh token for a new one and push the end of the session out by `lifetime`
+/// Every refresh token can be used once, seeing an old one again revokes the session it belongs to
+pub async fn rotate_refresh_token(
+    refresh_token: &str,
+    lifetime: Duration,
+) -> anyhow::Result<Rotation> {
+    let sid = match refresh_token_sid(refresh_token) {
+        Some(sid) => sid,
+        None => return Ok(Rotation::Invalid),
+    };
+    let conn = get_conn().await;
+    let hash = hash_token(refresh_token);
+
+    let mut result = conn
+        .query(
+            "SELECT user_id, refresh_hash, previous_refresh_hash,
+                rotated_utc >= strftime('%Y-%m-%d %H:%M:%S', 'now', :grace)
+            FROM sessions
+            WHERE sid = :sid AND expires_utc > strftime('%Y-%m-%d %H:%M:%S', 'now')",
+            named_params!(
+                ":sid": sid,
+                ":grace": format!("-{} seconds", ROTATION_GRACE_SECONDS),
+            ),
+        )
+        .await
+        .context("Failed to get session from database")?;
+
+    let (user_id, current, previous, recently_rotated) = match result.next().await? {
+        Some(row) => (
+            row.get::<i64>(0)?,
+            row.get::<Option<String>>(1)?,
+            row.get::<Option<String>>(2)?,
+            row.get::<Option<i64>>(3)? == Some(1),
+        ),
+        None => return Ok(Rotation::Invalid),
+    };
+
+    if current.as_deref() == Some(hash.as_str()) {
+        let next = new_refresh_token(sid);
+        // only rotate if nobody else did in the meantime
+        let rotated = conn
+            .execute(
+                "UPDATE sessions SET
+                    previous_refresh_hash = refresh_hash,
+                    refresh_hash = :next_hash,
+                    rotated_utc = strftime('%Y-%m-%d %H:%M:%S', 'now'),
+                    expires_utc = strftime('%Y-%m-%d %H:%M:%S', 'now', :lifetime)
+                WHERE sid = :sid AND refresh_hash = :hash",
+                named_params!(
+                    ":next_hash": hash_token(&next),
+                    ":lifetime": format!("+{} seconds", lifetime.as_secs()),
+                    ":sid": sid,
+                    ":hash": hash.as_str(),
+                ),
+            )
+            .await
+            .context("Failed to rotate refresh token in database")?;
+
+        return Ok(match rotated {
+            0 => Rotation::Concurrent { user_id },
+            _ => Rotation::Rotated {
+                user_id,
+                refresh_token: next,
+            },
+        });
+    }
+
+    if previous.as_deref() == Some(hash.as_str()) && recently_rotated {
+        return Ok(Rotation::Concurrent { user_id });
+    }
+
+    delete_session_by_sid(sid).await?;
+    Ok(Rotation::Reused)
+}
+
+/// The user of a session that is neither revoked nor expired, marking the session as seen
+pub async fn get_session_user_id(sid: &str) -> anyhow::Result<Option<i64>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT user_id, last_seen_utc < strftime('%Y-%m-%d %H:%M:%S', 'now', :resolution)
+            FROM sessions
+            WHERE sid = :sid AND expires_utc > strftime('%Y-%m-%d %H:%M:%S', 'now')",
+            named_params!(
+                ":sid": sid,
+                ":resolution": format!("-{} minutes", LAST_SEEN_RESOLUTION_MINUTES),
+            ),
+        )
+        .await
+        .context("Failed to get session from database")?;
+
+    let (user_id, stale) = match result.next().await? {
+        Some(row) => (row.get::<i64>(0)?, row.get::<Option<i64>>(1)? != Some(0)),
+        None => return Ok(None),
+    };
+
+    if stale {
+        conn.execute(
+            "UPDATE sessions SET last_seen_utc = (strftime('%Y-%m-%d %H:%M:%S', 'now')) WHERE sid = :sid",
+            named_params!(
+                ":sid": sid,
+            ),
+        )
+        .await
+        .context("Failed to update session in database")?;
+    }
+
+    Ok(Some(user_id))
+}
+
+pub async fn get_sessions(user_id: i64) -> anyhow::Result<Vec<SessionRow>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT id, sid, user_agent, ip, created_utc, last_seen_utc, expires_utc
+            FROM sessions
+            WHERE user_id = :user_id AND expires_utc > strftime('%Y-%m-%d %H:%M:%S', 'now')
+            ORDER BY last_seen_utc DESC",
+            named_params!(
+                ":user_id": user_id,
+            ),
+        )
+        .await
+        .context("Failed to get sessions from database")?;
+
+    let mut results: Vec<SessionRow> = vec![];
+    while let Ok(Some(r)) = result.next().await {
+        let row = libsql::de::from_row::<_>(&r);
+        if let Ok(row) = row {
+            results.push(row);
+        } else {
+            tracing::error!("Failed to deserialize row: {:?}", row);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Revoke one of a user's sessions, returning whether it existed
+pub async fn delete_session(user_id: i64, id: i64) -> anyhow::Result<bool> {
+    let conn = get_conn().await;
+
+    let deleted = conn
+        .execute(
+            "DELETE FROM sessions WHERE id = :id AND user_id = :user_id",
+            named_params!(
+                ":id": id,
+                ":user_id": user_id,
+            ),
+        )
+        .await
+        .context("Failed to delete session from database")?;
+
+    Ok(deleted > 0)
+}
+
+/// End the session a login token belongs to, on logout
+pub async fn delete_session_by_sid(sid: &str) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    conn.execute(
+        "DELETE FROM sessions WHERE sid = :sid",
+        named_params!(
+            ":sid": sid,
+        ),
+    )
+    .await
+    .context("Failed to delete session from database")?;
+
+    Ok(())
+}
+
+/// Log a user out everywhere, returning how many sessions were revoked
+pub async fn delete_user_sessions(user_id: i64) -> anyhow::Result<u64> {
+    let conn = get_conn().await;
+
+    conn.execute(
+        "DELETE FROM sessions WHERE user_id = :user_id",
+        named_params!(
+            ":user_id": user_id,
+        ),
+    )
+    .await
+    .context("Failed to delete sessions from database")
+}
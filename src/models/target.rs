@@ -0,0 +1,195 @@
+use anyhow::Context;
+use libsql::named_params;
+use rand::Rng;
+
+use crate::database::get_conn;
+
+/// One variant of a weighted split between several destinations of a redirect
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct TargetRow {
+    pub id: i64,
+    pub url: String,
+    pub weight: u32,
+    pub clicks: u64,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct TargetInput {
+    pub url: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Pick a variant at random, proportionally to its weight
+/// Variants with a weight of 0 are paused and never picked
+pub fn pick_weighted(targets: &[TargetRow]) -> Option<&TargetRow> {
+    let total: u64 = targets.iter().map(|t| t.weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rand::thread_rng().gen_range(0..total);
+    for target in targets {
+        if roll < target.weight as u64 {
+            return Some(target);
+        }
+        roll -= target.weight as u64;
+    }
+
+    None
+}
+
+pub async fn get_targets(redirect_id: i64) -> anyhow::Result<Vec<TargetRow>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT id, url, weight, clicks FROM redirect_targets WHERE redirect_id = :redirect_id ORDER BY id",
+            named_params!(
+                ":redirect_id": redirect_id,
+            ),
+        )
+        .await
+        .context("Failed to get redirect targets from database")?;
+
+    let mut results: Vec<TargetRow> = vec![];
+    while let Ok(Some(r)) = result.next().await {
+        let row = libsql::de::from_row::<_>(&r);
+        if let Ok(row) = row {
+            results.push(row);
+        } else {
+            tracing::error!("Failed to deserialize row: {:?}", row);
+        }
+    }
+
+    Ok(results)
+}
+
+pub async fn add_target(redirect_id: i64, target: &TargetInput) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    conn.execute(
+        "INSERT INTO redirect_targets (redirect_id, url, weight) VALUES (:redirect_id, :url, :weight)",
+        named_params!(
+            ":redirect_id": redirect_id,
+            ":url": target.url.as_str(),
+            ":weight": target.weight,
+        ),
+    )
+    .await
+    .context("Failed to insert redirect target into database")?;
+
+    Ok(())
+}
+
+pub async fn update_target(
+    redirect_id: i64,
+    target_id: i64,
+    target: &TargetInput,
+) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    let result = conn
+        .execute(
+            "UPDATE redirect_targets SET url = :url, weight = :weight WHERE id = :id AND redirect_id = :redirect_id",
+            named_params!(
+                ":id": target_id,
+                ":redirect_id": redirect_id,
+                ":url": target.url.as_str(),
+                ":weight": target.weight,
+            ),
+        )
+        .await
+        .context("Failed to update redirect target in database")?;
+
+    match result {
+        1 => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "Failed to update redirect target in database"
+        )),
+    }
+}
+
+pub async fn delete_target(redirect_id: i64, target_id: i64) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    let result = conn
+        .execute(
+            "DELETE FROM redirect_targets WHERE id = :id AND redirect_id = :redirect_id",
+            named_params!(
+                ":id": target_id,
+                ":redirect_id": redirect_id,
+            ),
+        )
+        .await
+        .context("Failed to delete redirect target from database")?;
+
+    match result {
+        1 => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "Failed to delete redirect target from database"
+        )),
+    }
+}
+
+pub async fn inc_clicks(target_id: i64) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    let result = conn
+        .execute(
+            "UPDATE redirect_targets SET clicks = clicks + 1 WHERE id = :id",
+            named_params!(
+                ":id": target_id,
+            ),
+        )
+        .await
+        .context("Failed to increment target clicks in database")?;
+
+    match result {
+        1 => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "Failed to increment target clicks in database"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick_weighted, TargetRow};
+
+    fn target(id: i64, weight: u32) -> TargetRow {
+        TargetRow {
+            id,
+            url: format!("https://example.com/{}", id),
+            weight,
+            clicks: 0,
+        }
+    }
+
+    #[test]
+    fn never_picks_paused_variants() {
+        let targets = [target(1, 0), target(2, 3), target(3, 0), target(4, 1)];
+        for _ in 0..1000 {
+            let picked = pick_weighted(&targets).unwrap();
+            assert!(picked.weight > 0, "picked paused variant {}", picked.id);
+        }
+    }
+
+    #[test]
+    fn picks_the_only_running_variant() {
+        let targets = [target(1, 0), target(2, 5)];
+        for _ in 0..100 {
+            assert_eq!(pick_weighted(&targets).unwrap().id, 2);
+        }
+    }
+
+    #[test]
+    fn nothing_to_pick_when_all_paused() {
+        assert!(pick_weighted(&[target(1, 0), target(2, 0)]).is_none());
+        assert!(pick_weighted(&[]).is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use libsql::named_params;
+use sha2::{Digest, Sha256};
+
+use crate::{database::get_conn, utils::strings::generate_random_string};
+
+use super::date::custom_date_format;
+
+/// Prefix of every API token, so leaked tokens are easy to spot
+pub const TOKEN_PREFIX: &str = "shd_";
+const TOKEN_LENGTH: usize = 40;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct TokenRow {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "custom_date_format")]
+    pub created_utc: DateTime<Utc>,
+    pub last_used_utc: Option<String>,
+}
+
+/// Hex sha256 of a secret, tables only keep these so a leaked database doesn't leak logins
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Create a new API token for a user
+/// The plain token is only returned here, the database keeps nothing but its hash
+pub async fn create_token(user_id: i64, name: &str) -> anyhow::Result<(TokenRow, String)> {
+    let conn = get_conn().await;
+    let token = format!("{}{}", TOKEN_PREFIX, generate_random_string(TOKEN_LENGTH));
+
+    conn.execute(
+        "INSERT INTO api_tokens (user_id, name, token_hash) VALUES (:user_id, :name, :token_hash)",
+        named_params!(
+            ":user_id": user_id,
+            ":name": name,
+            ":token_hash": hash_token(&token),
+        ),
+    )
+    .await
+    .context("Failed to insert API token into database")?;
+
+    let id = conn.last_insert_rowid();
+    let row = get_tokens(user_id)
+        .await?
+        .into_iter()
+        .find(|t| t.id == id)
+        .context("Failed to get the new API token")?;
+
+    Ok((row, token))
+}
+
+pub async fn get_tokens(user_id: i64) -> anyhow::Result<Vec<TokenRow>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT id, name, created_utc, last_used_utc FROM api_tokens WHERE user_id = :user_id ORDER BY id",
+            named_params!(
+                ":user_id": user_id,
+            ),
+        )
+        .await
+        .context("Failed to get API tokens from database")?;
+
+    let mut results: Vec<TokenRow> = vec![];
+    while let Ok(Some(r)) = result.next().await {
+        let row = libsql::de::from_row::<_>(&r);
+        if let Ok(row) = row {
+            results.push(row);
+        } else {
+            tracing::error!("Failed to deserialize row: {:?}", row);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Look up the user a token belongs to, marking the token as used
+pub async fn get_user_id_by_token(token: &str) -> anyhow::Result<Option<i64>> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+
+    let conn = get_conn().await;
+    let token_hash = hash_token(token);
+
+    let mut result = conn
+        .query(
+            "SELECT user_id FROM api_tokens WHERE token_hash = :token_hash",
+            named_params!(
+                ":token_hash": token_hash.as_str(),
+            ),
+        )
+        .await
+        .context("Failed to get API token from database")?;
+
+    let user_id = match result.next().await? {
+        Some(row) => row.get::<i64>(0)?,
+        None => return Ok(None),
+    };
+
+    conn.execute(
+        "UPDATE api_tokens SET last_used_utc = (strftime('%Y-%m-%d %H:%M:%S', 'now')) WHERE token_hash = :token_hash",
+        named_params!(
+            ":token_hash": token_hash.as_str(),
+        ),
+    )
+    .await
+    .context("Failed to update API token in database")?;
+
+    Ok(Some(user_id))
+}
+
+/// Revoke one of a user's tokens, returning whether it existed
+pub async fn delete_token(user_id: i64, id: i64) -> anyhow::Result<bool> {
+    let conn = get_conn().await;
+
+    let deleted = conn
+        .execute(
+            "DELETE FROM api_tokens WHERE id = :id AND user_id = :user_id",
+            named_params!(
+                ":id": id,
+                ":user_id": user_id,
+            ),
+        )
+        .await
+        .context("Failed to delete API token from database")?;
+
+    Ok(deleted > 0)
+}
@@ -4,14 +4,23 @@ use libsql::named_params;
 
 use crate::database;
 
-use super::date::custom_date_format;
+use super::{date::custom_date_format, identity};
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_MEMBER: &str = "member";
+/// Can look at links but not create or change them
+pub const ROLE_VIEWER: &str = "viewer";
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[allow(dead_code)]
 pub struct UserRow {
     pub id: i64,
-    pub discord_snowflake: String,
+    /// Only set for users who log in with Discord
+    pub discord_snowflake: Option<String>,
     pub discord_username: String,
+    pub role: String,
+    /// Disabled users can't log in and their tokens are rejected
+    pub disabled: bool,
     #[serde(with = "custom_date_format")]
     pub created_utc: DateTime<Utc>,
     #[serde(with = "custom_date_format")]
@@ -72,3 +81,84 @@ pub async fn get_user_by_discord_id(snowflake: &str) -> anyhow::Result<UserRow>
         Ok(None) => return Err(anyhow::anyhow!("Failed to get user by discord id")),
     }
 }
+
+/// Look a user up by Discord snowflake, or by `<provider>:<subject>` for an account at any login provider
+pub async fn find_user(login: &str) -> anyhow::Result<UserRow> {
+    match login.split_once(':') {
+        Some((provider, subject)) => {
+            match identity::get_user_id_by_identity(provider, subject).await? {
+                Some(user_id) => get_user_by_id(user_id).await,
+                None => Err(anyhow::anyhow!(
+                    "No user with {} account {}, they have to log in once first",
+                    provider,
+                    subject
+                )),
+            }
+        }
+        None => get_user_by_discord_id(login).await.map_err(|_| {
+            anyhow::anyhow!(
+                "No user with Discord id {}, they have to log in once first",
+                login
+            )
+        }),
+    }
+}
+
+pub async fn get_user_by_id(id: i64) -> anyhow::Result<UserRow> {
+    let conn = database::get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT * FROM users WHERE id = :id LIMIT 1",
+            named_params!(
+                ":id": id,
+            ),
+        )
+        .await
+        .context("Failed to get user from database")?;
+
+    match result.next().await {
+        Ok(Some(row)) => {
+            let row = libsql::de::from_row::<_>(&row)?;
+            Ok(row)
+        }
+        Err(e) => Err(anyhow::anyhow!("Failed to get user by id: {}", e)),
+        Ok(None) => Err(anyhow::anyhow!("Failed to get user by id")),
+    }
+}
+
+/// Disable or re-enable a user
+pub async fn set_user_disabled(user_id: i64, disabled: bool) -> anyhow::Result<UserRow> {
+    let conn = database::get_conn().await;
+
+    conn.execute(
+        "UPDATE users SET disabled = :disabled, updated_utc = (strftime('%Y-%m-%d %H:%M:%S', 'now'))
+        WHERE id = :id",
+        named_params! {
+            ":disabled": disabled,
+            ":id": user_id,
+        },
+    )
+    .await
+    .context("Failed to update user in database")?;
+
+    get_user_by_id(user_id).await
+}
+
+/// Change the role of a user
+pub async fn set_user_role(user_id: i64, role: &str) -> anyhow::Result<UserRow> {
+    let conn = database::get_conn().await;
+
+    conn.execute(
+        "UPDATE users SET role = :role, updated_utc = (strftime('%Y-%m-%d %H:%M:%S', 'now'))
+        WHERE id = :id",
+        named_params! {
+            ":role": role,
+            ":id": user_id,
+        },
+    )
+    .await
+    .context("Failed to update user role in database")?;
+
+    get_user_by_id(user_id).await
+}
@@ -0,0 +1,68 @@
+use anyhow::Context;
+use libsql::named_params;
+
+use crate::database::get_conn;
+
+/// A single hit on a redirect, recorded after the client has been sent on its way
+#[derive(Debug, Default)]
+pub struct NewVisit {
+    pub redirect_id: i64,
+    pub locale: Option<String>,
+    pub target_id: Option<i64>,
+    pub country: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct CountryCount {
+    pub country: Option<String>,
+    pub visits: u64,
+}
+
+pub async fn record_visit(visit: &NewVisit) -> anyhow::Result<()> {
+    let conn = get_conn().await;
+
+    conn.execute(
+        "INSERT INTO visits (redirect_id, locale, target_id, country)
+        VALUES (:redirect_id, :locale, :target_id, :country)",
+        named_params!(
+            ":redirect_id": visit.redirect_id,
+            ":locale": visit.locale.as_deref(),
+            ":target_id": visit.target_id,
+            ":country": visit.country.as_deref(),
+        ),
+    )
+    .await
+    .context("Failed to record visit in database")?;
+
+    Ok(())
+}
+
+/// Number of visits of a redirect per country, visits from unknown countries are grouped under `null`
+pub async fn count_by_country(redirect_id: i64) -> anyhow::Result<Vec<CountryCount>> {
+    let conn = get_conn().await;
+
+    let mut result = conn
+        .query(
+            "SELECT country, COUNT(*) AS visits FROM visits
+            WHERE redirect_id = :redirect_id
+            GROUP BY country
+            ORDER BY visits DESC",
+            named_params!(
+                ":redirect_id": redirect_id,
+            ),
+        )
+        .await
+        .context("Failed to count visits by country")?;
+
+    let mut results: Vec<CountryCount> = vec![];
+    while let Ok(Some(r)) = result.next().await {
+        let row = libsql::de::from_row::<_>(&r);
+        if let Ok(row) = row {
+            results.push(row);
+        } else {
+            tracing::error!("Failed to deserialize row: {:?}", row);
+        }
+    }
+
+    Ok(results)
+}
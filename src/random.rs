@@ -0,0 +1,19 @@
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Generate a random string of lowercase letters and digits of a given length
+/// # Examples
+/// ```
+/// use shidou::random::generate_random_string;
+/// let rand_str = generate_random_string(5);
+/// assert_eq!(rand_str.len(), 5);
+/// ```
+pub fn generate_random_string(length: usize) -> String {
+    let rand_string: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        .take(length)
+        .map(char::from)
+        .collect();
+
+    rand_string
+}
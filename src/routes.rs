@@ -1,29 +1,89 @@
-use axum::{routing::get, Router};
+use axum::{
+    routing::{delete, get, post, put},
+    Router,
+};
 use tower_http::services::{ServeDir, ServeFile};
 
+use crate::config::{AppState, RateLimit};
 use crate::handlers;
 use crate::handlers::api;
 use crate::handlers::auth;
 use crate::handlers::components;
-use crate::middleware::auth::auth_cookie_middleware;
+use crate::middleware::auth::{
+    admin_middleware, auth_cookie_middleware, editor_middleware, session_refresh_middleware,
+};
+use crate::middleware::public_url::public_url_middleware;
+use crate::middleware::rate_limit::{rate_limit_middleware, RateLimiter};
 
-pub fn main_router() -> Router {
+pub fn main_router(state: AppState) -> Router {
     tracing::debug!("initializing router(s) ...");
 
+    // with a metrics port of its own, /metrics is only served by `metrics_router`
+    let metrics = match state.config.metrics.port {
+        Some(_) => Router::new(),
+        None => Router::new().route("/metrics", get(handlers::metrics::get)),
+    };
+
     Router::new()
-        .route("/", get(handlers::home::get))
-        .route("/*key", get(handlers::redirect::get))
-        .route("/healthcheck", get(|| async { "Ok" }))
+        .route(
+            "/",
+            get(handlers::home::get).layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                session_refresh_middleware,
+            )),
+        )
+        .merge(redirect_router(&state))
+        .route("/healthcheck", get(handlers::health::livez))
+        .route("/livez", get(handlers::health::livez))
+        .route("/readyz", get(handlers::health::readyz))
+        .merge(metrics)
         .merge(services_router())
-        .nest("/auth", auth_router())
-        .nest("/api", api_router())
-        .nest("/ui", component_router())
+        .nest("/auth", auth_router(&state))
+        .nest("/api", api_router(state.clone()))
+        .nest("/ui", component_router(state.clone()))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            public_url_middleware,
+        ))
+        .with_state(state)
+}
+
+/**
+ * wrap a router in the token buckets of its group, unless limiting is off for it
+**/
+fn rate_limited(
+    router: Router<AppState>,
+    name: &'static str,
+    limit: Option<RateLimit>,
+    state: &AppState,
+) -> Router<AppState> {
+    match limit {
+        Some(limit) => router.layer(axum::middleware::from_fn_with_state(
+            RateLimiter::new(name, limit, &state.config.trusted_proxies),
+            rate_limit_middleware,
+        )),
+        None => router,
+    }
+}
+
+fn redirect_router(state: &AppState) -> Router<AppState> {
+    let router = Router::new().route("/*key", get(handlers::redirect::get));
+    rate_limited(router, "redirect", state.config.rate_limit.redirect, state)
+}
+
+/**
+ * router for the separate metrics port
+**/
+pub fn metrics_router(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(handlers::metrics::get))
+        .with_state(state)
 }
 
 /**
  * router for the static assets and such
 **/
-pub fn services_router() -> Router {
+pub fn services_router() -> Router<AppState> {
     let assets_path = match std::env::current_dir() {
         Ok(path) => path,
         Err(_) => std::path::PathBuf::from("./"),
@@ -39,18 +99,22 @@ pub fn services_router() -> Router {
         .nest_service("/site.webmanifest", ServeFile::new(manifest_path))
 }
 
-fn auth_router() -> Router {
-    Router::new()
+fn auth_router(state: &AppState) -> Router<AppState> {
+    let router = Router::new()
         .route("/login", get(auth::get_login_redirect))
+        .route("/login/:provider", get(auth::get_login_redirect))
         .route("/logout", get(auth::logout))
-        .route("/callback", get(auth::callback))
+        .route("/callback", get(auth::callback));
+    rate_limited(router, "auth", state.config.rate_limit.auth, state)
 }
 
 /**
  * router for our api routes and the strava setup routes
  **/
-fn api_router() -> Router {
-    Router::new()
+fn api_router(state: AppState) -> Router<AppState> {
+    // viewers can look at links but not change them
+    let links = Router::new()
+        .route("/import", post(api::import::post))
         .route(
             "/redirect",
             get(api::redirect::get)
@@ -58,14 +122,61 @@ fn api_router() -> Router {
                 .put(api::redirect::put)
                 .delete(api::redirect::delete),
         )
-        .layer(axum::middleware::from_fn(auth_cookie_middleware))
+        .route("/redirect/:key", get(api::redirect::get_one))
+        .route("/redirect/:key/qr", get(api::redirect::get_qr))
+        .route(
+            "/redirect/:key/rules",
+            get(api::redirect::get_rules).put(api::redirect::put_rules),
+        )
+        .route(
+            "/redirect/:key/locales",
+            get(api::redirect::get_locales).put(api::redirect::put_locales),
+        )
+        .route(
+            "/redirect/:key/countries",
+            get(api::redirect::get_countries),
+        )
+        .route(
+            "/redirect/:key/targets",
+            get(api::redirect::get_targets).post(api::redirect::post_target),
+        )
+        .route(
+            "/redirect/:key/targets/:id",
+            put(api::redirect::put_target).delete(api::redirect::delete_target),
+        )
+        .route_layer(axum::middleware::from_fn(editor_middleware));
+
+    let router = Router::new()
+        .route("/tokens", get(api::token::get).post(api::token::post))
+        .route("/tokens/:id", delete(api::token::delete))
+        .route("/sessions", get(api::session::get))
+        .route("/sessions/:id", delete(api::session::delete))
+        .route(
+            "/users/:login/sessions",
+            delete(api::session::delete_user_sessions)
+                .layer(axum::middleware::from_fn(admin_middleware)),
+        )
+        .route(
+            "/export",
+            get(api::export::get).layer(axum::middleware::from_fn(admin_middleware)),
+        )
+        .merge(links);
+
+    // limited per user, so the limiter has to run after authentication
+    rate_limited(router, "api", state.config.rate_limit.api, &state).layer(
+        axum::middleware::from_fn_with_state(state, auth_cookie_middleware),
+    )
 }
 
-fn component_router() -> Router {
+fn component_router(state: AppState) -> Router<AppState> {
     Router::new()
         .route(
             "/redirect_url_input",
             get(components::redirect_url_input::get),
         )
-        .layer(axum::middleware::from_fn(auth_cookie_middleware))
+        .route("/sessions", get(components::sessions::get))
+        .layer(axum::middleware::from_fn_with_state(
+            state,
+            auth_cookie_middleware,
+        ))
 }
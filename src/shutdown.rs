@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::database;
+
+/// Resolve once SIGTERM (sent by Fly on deploys) or SIGINT (Ctrl-C) arrives
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {:?}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {:?}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Cancel `shutdown` on the first signal, resolving to the moment the drain timeout runs out
+pub async fn deadline_on_signal(shutdown: CancellationToken, timeout: Duration) -> Instant {
+    signal().await;
+    shutdown.cancel();
+    Instant::now() + timeout
+}
+
+/// Let the background tasks finish within what is left of the drain timeout, then sync the replica one last time
+pub async fn finish(tasks: TaskTracker, deadline: Instant) {
+    tasks.close();
+    if !tasks.is_empty() {
+        tracing::info!("Waiting for {} background tasks", tasks.len());
+    }
+    if tokio::time::timeout_at(deadline, tasks.wait())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "Drain timeout reached, abandoning {} background tasks",
+            tasks.len()
+        );
+    }
+
+    match database::sync().await {
+        Ok(()) => tracing::info!("Synced database replica"),
+        Err(err) => tracing::error!("Final database sync failed: {:?}", err),
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use axum::http::HeaderMap;
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use opentelemetry::{global, propagation::Extractor, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+};
+
+use crate::{config::LogFormat, utils::logfmt};
+
+static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Request and query latencies are mostly sub-millisecond reads from the local replica
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Send logs to stdout in the given format, filtered by `RUST_LOG`
+/// With an OTLP endpoint the spans are exported to it as well
+pub fn init_logging(format: LogFormat, otlp_endpoint: Option<&str>) -> anyhow::Result<()> {
+    let env_filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+    let otel = match otlp_endpoint {
+        Some(endpoint) => Some(tracing_opentelemetry::layer().with_tracer(otlp_tracer(endpoint)?)),
+        None => None,
+    };
+    let registry = tracing_subscriber::registry().with(env_filter).with(otel);
+
+    match format {
+        LogFormat::Text => registry.with(fmt::layer()).init(),
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(true)
+                    .with_span_list(false),
+            )
+            .init(),
+        LogFormat::Logfmt => registry
+            .with(
+                fmt::layer()
+                    .event_format(logfmt::Logfmt)
+                    .fmt_fields(logfmt::fields()),
+            )
+            .init(),
+    }
+
+    Ok(())
+}
+
+fn otlp_tracer(endpoint: &str) -> anyhow::Result<trace::Tracer> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new([
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ])))
+        .install_batch(runtime::Tokio)
+        .context("Failed to set up OTLP export")
+}
+
+/// Export the spans still buffered, a no-op when OTLP export is off
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// The W3C `traceparent` of the caller, so our spans join its trace
+pub fn remote_context(headers: &HeaderMap) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Install the Prometheus recorder, metrics recorded before this are dropped
+pub fn init_metrics() -> anyhow::Result<()> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("duration_seconds".to_string()),
+            &LATENCY_BUCKETS,
+        )
+        .context("Failed to set histogram buckets")?
+        .install_recorder()
+        .context("Failed to install metrics recorder")?;
+
+    describe_counter!(
+        "http_requests_total",
+        "Requests handled, by method, route and status"
+    );
+    describe_histogram!(
+        "http_request_duration_seconds",
+        Unit::Seconds,
+        "Time to produce a response, by method, route and status"
+    );
+    describe_counter!(
+        "shidou_redirects_total",
+        "Short link lookups, by result hit or miss"
+    );
+    describe_counter!(
+        "shidou_redirect_cache_total",
+        "Redirect lookups, by whether the in-memory cache had them"
+    );
+    describe_gauge!(
+        "shidou_redirect_cache_hit_ratio",
+        "Share of redirect lookups answered by the in-memory cache since startup"
+    );
+    describe_histogram!(
+        "shidou_db_query_duration_seconds",
+        Unit::Seconds,
+        "Database query latency, by query"
+    );
+    describe_gauge!(
+        "shidou_visit_flush_backlog",
+        "Visits handed to background tasks and not yet written to the database"
+    );
+    describe_counter!(
+        "shidou_rate_limited_total",
+        "Requests turned away with 429, by policy"
+    );
+    describe_counter!(
+        "shidou_logins_total",
+        "Login attempts, by result and failure reason"
+    );
+
+    PROMETHEUS
+        .set(handle)
+        .map_err(|_| anyhow::anyhow!("Metrics are already initialized"))
+}
+
+/// The current metrics in the Prometheus text format
+pub fn render_metrics() -> String {
+    PROMETHEUS
+        .get()
+        .map(|handle| handle.render())
+        .unwrap_or_default()
+}
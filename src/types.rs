@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// Custom OpenGraph metadata shown when a link is unfurled by a chat app or social network
+/// A `PUT` leaves missing fields as they are and clears empty ones
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct OpenGraph {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub og_title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub og_description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub og_image: Option<String>,
+}
+
+/// Body of `POST` and `PUT /api/redirect`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectInput {
+    pub key: String,
+    pub url: String,
+    /// Off for new redirects and unchanged by a `PUT` when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interstitial: Option<bool>,
+    #[serde(flatten)]
+    pub og: OpenGraph,
+}
+
+/// Body of `DELETE /api/redirect`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteRedirectInput {
+    pub key: String,
+}
+
+/// Body of `POST /api/tokens`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInput {
+    pub name: String,
+}
+
+/// Response of `POST /api/tokens`, the only time the token itself is shown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewToken {
+    pub id: i64,
+    pub name: String,
+    pub token: String,
+}
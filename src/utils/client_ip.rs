@@ -0,0 +1,120 @@
+use std::net::IpAddr;
+
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+
+pub fn is_trusted(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+/// Resolve the address of the client behind any trusted proxies
+/// Forwarding headers are only believed when the connecting peer is a trusted proxy
+pub fn get_client_ip(headers: &HeaderMap, peer: IpAddr, trusted_proxies: &[IpNet]) -> IpAddr {
+    if !is_trusted(&peer, trusted_proxies) {
+        return peer;
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(ip) = header("fly-client-ip").and_then(|ip| ip.trim().parse().ok()) {
+        return ip;
+    }
+
+    if let Some(forwarded_for) = header("x-forwarded-for") {
+        let hops: Vec<IpAddr> = forwarded_for
+            .split(',')
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        // every proxy appends the address it received the request from,
+        // so the client is the right-most hop that isn't one of our proxies
+        if let Some(ip) = hops
+            .iter()
+            .rev()
+            .find(|ip| !is_trusted(ip, trusted_proxies))
+        {
+            return *ip;
+        }
+        if let Some(ip) = hops.first() {
+            return *ip;
+        }
+    }
+
+    peer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use axum::http::HeaderMap;
+    use ipnet::IpNet;
+
+    use super::get_client_ip;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn proxies() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn untrusted_peers_are_not_believed() {
+        let headers = headers(&[("fly-client-ip", "1.1.1.1"), ("x-forwarded-for", "2.2.2.2")]);
+
+        assert_eq!(
+            get_client_ip(&headers, ip("203.0.113.9"), &proxies()),
+            ip("203.0.113.9")
+        );
+        assert_eq!(get_client_ip(&headers, ip("10.0.0.1"), &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn fly_client_ip_wins_from_a_trusted_proxy() {
+        let headers = headers(&[("fly-client-ip", "1.1.1.1"), ("x-forwarded-for", "2.2.2.2")]);
+
+        assert_eq!(
+            get_client_ip(&headers, ip("10.0.0.1"), &proxies()),
+            ip("1.1.1.1")
+        );
+    }
+
+    #[test]
+    fn right_most_untrusted_hop_of_x_forwarded_for() {
+        // the client made up the first hop, our proxies appended the rest
+        let headers = headers(&[("x-forwarded-for", "6.6.6.6, 198.51.100.7, 10.0.0.2")]);
+
+        assert_eq!(
+            get_client_ip(&headers, ip("10.0.0.1"), &proxies()),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn all_hops_trusted_falls_back_to_the_first() {
+        let headers = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+
+        assert_eq!(
+            get_client_ip(&headers, ip("10.0.0.1"), &proxies()),
+            ip("10.0.0.3")
+        );
+    }
+
+    #[test]
+    fn unparsable_headers_fall_back_to_the_peer() {
+        let headers = headers(&[("fly-client-ip", "nope"), ("x-forwarded-for", "nope")]);
+
+        assert_eq!(
+            get_client_ip(&headers, ip("10.0.0.1"), &proxies()),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -1,6 +1,17 @@
 use anyhow::Context;
+use askama::filters::urlencode;
+use axum::async_trait;
+use tracing::Instrument;
 
-use super::env::GuildID;
+use crate::{
+    config::{DiscordConfig, GuildID},
+    models::user::{ROLE_ADMIN, ROLE_MEMBER, ROLE_VIEWER},
+    utils::oauth::{Identity, LoginError, LoginFlow, LoginProvider},
+};
+
+const SCOPES: [&str; 2] = ["identify", "guilds"];
+/// Lets us read the roles of the user in a guild
+const MEMBER_SCOPE: &str = "guilds.members.read";
 
 pub struct DiscordUser {
     pub id: String,
@@ -34,6 +45,7 @@ pub struct PartialGuild {
     pub name: String,
 }
 
+#[tracing::instrument(name = "discord.get_user_info", skip_all, fields(otel.kind = "client"))]
 pub async fn get_user_info_by_token(token: &str) -> anyhow::Result<DiscordUser> {
     let client = reqwest::Client::new();
 
@@ -63,3 +75,201 @@ pub async fn get_user_info_by_token(token: &str) -> anyhow::Result<DiscordUser>
         guilds,
     })
 }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct PartialGuildMember {
+    pub roles: Vec<String>,
+}
+
+/// Role ids the user has in a guild they are a member of, needs the `guilds.members.read` scope
+#[tracing::instrument(name = "discord.get_guild_member", skip_all, fields(otel.kind = "client"))]
+pub async fn get_guild_member_roles(
+    token: &str,
+    guild_id: &GuildID,
+) -> anyhow::Result<Vec<String>> {
+    let member = reqwest::Client::new()
+        .get(format!(
+            "https://discord.com/api/users/@me/guilds/{}/member",
+            guild_id.as_str()
+        ))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .context("failed to fetch guild member from discord")?
+        .error_for_status()
+        .context("failed to fetch guild member from discord")?
+        .json::<PartialGuildMember>()
+        .await
+        .context("failed to parse guild member into structs")?;
+
+    Ok(member.roles)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Log in with Discord, limited to members of the allowed guilds when there are any,
+/// and to members with one of the allowed roles in guilds that have them
+pub struct DiscordProvider {
+    config: DiscordConfig,
+}
+
+impl DiscordProvider {
+    pub fn new(config: DiscordConfig) -> DiscordProvider {
+        DiscordProvider { config }
+    }
+
+    fn scopes(&self) -> Vec<&'static str> {
+        let mut scopes = SCOPES.to_vec();
+        // only asked for when roles decide who gets in or what they may do
+        if self.config.needs_roles() {
+            scopes.push(MEMBER_SCOPE);
+        }
+        scopes
+    }
+
+    /// Whether the roles of a member let them in through `guild`, guilds without allowed roles let everyone in
+    fn roles_allowed(&self, guild: &GuildID, roles: &[String]) -> bool {
+        match self
+            .config
+            .guild_roles
+            .iter()
+            .find(|(allowed_guild, _)| allowed_guild.as_str() == guild.as_str())
+        {
+            Some((_, allowed)) => roles.iter().any(|role| allowed.contains(role)),
+            None => true,
+        }
+    }
+
+    /// The shidou role for the Discord roles of a user, the highest one wins
+    /// Users without any mapped role become members, so losing a role in Discord takes the shidou role away too
+    fn map_role(&self, roles: &[String]) -> Option<&'static str> {
+        let role_map = &self.config.role_map;
+        if role_map.is_empty() {
+            return None;
+        }
+
+        let has_any = |mapped: &[String]| roles.iter().any(|role| mapped.contains(role));
+        Some(if has_any(&role_map.admin) {
+            ROLE_ADMIN
+        } else if has_any(&role_map.member) {
+            ROLE_MEMBER
+        } else if has_any(&role_map.viewer) {
+            ROLE_VIEWER
+        } else {
+            ROLE_MEMBER
+        })
+    }
+
+    async fn exchange_code(&self, code: &str, redirect_uri: &str) -> anyhow::Result<TokenResponse> {
+        let res = reqwest::Client::new()
+            .post("https://discord.com/api/oauth2/token")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .form(&[
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("code", code),
+                ("scope", &self.scopes().join("+")),
+                ("grant_type", "authorization_code"),
+            ])
+            .send()
+            .instrument(tracing::info_span!(
+                "discord.exchange_code",
+                otel.kind = "client"
+            ))
+            .await
+            .context("failed to reach discord")?;
+
+        if !res.status().is_success() {
+            let err_text = res.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("discord refused the code: {}", err_text));
+        }
+
+        res.json::<TokenResponse>()
+            .await
+            .context("failed to parse token response")
+    }
+}
+
+#[async_trait]
+impl LoginProvider for DiscordProvider {
+    fn id(&self) -> &'static str {
+        "discord"
+    }
+
+    fn display_name(&self) -> &str {
+        "Discord"
+    }
+
+    async fn authorize_url(&self, flow: &LoginFlow, redirect_uri: &str) -> anyhow::Result<String> {
+        // Discord doesn't document PKCE, the state alone ties the callback to this browser
+        Ok(format!(
+            "https://discord.com/oauth2/authorize?client_id={}&response_type=code&redirect_uri={}&scope={}&state={}",
+            self.config.client_id,
+            urlencode(redirect_uri)?,
+            self.scopes().join("%20"),
+            flow.state,
+        ))
+    }
+
+    async fn identify(
+        &self,
+        code: &str,
+        _flow: &LoginFlow,
+        redirect_uri: &str,
+    ) -> Result<Identity, LoginError> {
+        let token_response = self
+            .exchange_code(code, redirect_uri)
+            .await
+            .map_err(LoginError::TokenExchange)?;
+
+        let user_info = get_user_info_by_token(&token_response.access_token)
+            .await
+            .map_err(LoginError::UserInfo)?;
+
+        if !self.config.guilds.is_empty() && !user_info.has_any_guild(&self.config.guilds) {
+            return Err(LoginError::Forbidden {
+                reason: "guild",
+                message: "You are not a member of an allowed Discord guild".to_string(),
+            });
+        }
+        if !self.config.needs_roles() {
+            return Ok(Identity {
+                subject: user_info.id,
+                username: user_info.username,
+                role: None,
+            });
+        }
+
+        // roles of the allowed guilds the user is in and that let them in
+        let mut roles = vec![];
+        let mut allowed = false;
+        for guild in &self.config.guilds {
+            if !user_info.has_guild(guild.clone()) {
+                continue;
+            }
+            let guild_roles = get_guild_member_roles(&token_response.access_token, guild)
+                .await
+                .map_err(LoginError::UserInfo)?;
+            if self.roles_allowed(guild, &guild_roles) {
+                allowed = true;
+                roles.extend(guild_roles);
+            }
+        }
+        if !allowed {
+            return Err(LoginError::Forbidden {
+                reason: "role",
+                message: "You don't have a Discord role that is allowed to log in".to_string(),
+            });
+        }
+
+        Ok(Identity {
+            subject: user_info.id,
+            username: user_info.username,
+            role: self.map_role(&roles),
+        })
+    }
+}
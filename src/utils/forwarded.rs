@@ -0,0 +1,140 @@
+use axum::http::HeaderMap;
+
+/// What a reverse proxy told us about the original request
+#[derive(Debug, Default, PartialEq)]
+pub struct Forwarded {
+    pub proto: Option<String>,
+    pub host: Option<String>,
+}
+
+/// Read the scheme and host of the original request from `Forwarded` (RFC 7239),
+/// falling back to `X-Forwarded-Proto` and `X-Forwarded-Host`
+/// Only call this for requests coming from a trusted proxy, the headers are trivial to spoof otherwise
+pub fn parse(headers: &HeaderMap) -> Forwarded {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    // the first element was added by the proxy closest to the client
+    let mut forwarded = header("forwarded")
+        .and_then(|value| value.split(',').next())
+        .map(parse_element)
+        .unwrap_or_default();
+
+    let first = |value: &str| {
+        value
+            .split(',')
+            .next()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    if forwarded.proto.is_none() {
+        forwarded.proto = header("x-forwarded-proto").and_then(first);
+    }
+    if forwarded.host.is_none() {
+        forwarded.host = header("x-forwarded-host").and_then(first);
+    }
+
+    forwarded.proto = forwarded
+        .proto
+        .map(|proto| proto.to_ascii_lowercase())
+        .filter(|proto| proto == "http" || proto == "https");
+    forwarded
+}
+
+/// Parse one `for=...;proto=https;host=example.com` element
+fn parse_element(element: &str) -> Forwarded {
+    let mut forwarded = Forwarded::default();
+
+    for pair in element.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match name.trim().to_ascii_lowercase().as_str() {
+            "proto" => forwarded.proto = Some(value),
+            "host" => forwarded.host = Some(value),
+            _ => {}
+        }
+    }
+
+    forwarded
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+
+    use super::{parse, Forwarded};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded() {
+        let forwarded = parse(&headers(&[
+            (
+                "forwarded",
+                "for=1.2.3.4;proto=https;host=\"go.example.com\"",
+            ),
+            ("x-forwarded-proto", "http"),
+            ("x-forwarded-host", "evil.example.com"),
+        ]));
+
+        assert_eq!(
+            forwarded,
+            Forwarded {
+                proto: Some("https".to_string()),
+                host: Some("go.example.com".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn falls_back_to_x_forwarded_per_field() {
+        let forwarded = parse(&headers(&[
+            ("forwarded", "for=1.2.3.4;proto=https"),
+            ("x-forwarded-proto", "http"),
+            ("x-forwarded-host", "go.example.com, proxy.internal"),
+        ]));
+
+        assert_eq!(forwarded.proto.as_deref(), Some("https"));
+        assert_eq!(forwarded.host.as_deref(), Some("go.example.com"));
+    }
+
+    #[test]
+    fn uses_the_first_forwarded_element() {
+        let forwarded = parse(&headers(&[(
+            "forwarded",
+            "proto=https;host=go.example.com, proto=http;host=proxy.internal",
+        )]));
+
+        assert_eq!(forwarded.proto.as_deref(), Some("https"));
+        assert_eq!(forwarded.host.as_deref(), Some("go.example.com"));
+    }
+
+    #[test]
+    fn drops_protos_other_than_http_and_https() {
+        assert_eq!(
+            parse(&headers(&[("forwarded", "proto=javascript")])).proto,
+            None
+        );
+        assert_eq!(parse(&headers(&[("x-forwarded-proto", "ftp")])).proto, None);
+        assert_eq!(
+            parse(&headers(&[("x-forwarded-proto", "HTTPS")]))
+                .proto
+                .as_deref(),
+            Some("https")
+        );
+    }
+
+    #[test]
+    fn nothing_forwarded() {
+        assert_eq!(parse(&HeaderMap::new()), Forwarded::default());
+    }
+}
@@ -0,0 +1,42 @@
+use std::{net::IpAddr, sync::OnceLock};
+
+use maxminddb::{geoip2, Reader};
+
+static GEOIP: OnceLock<Option<Reader<Vec<u8>>>> = OnceLock::new();
+
+/// Load the GeoIP database, leaving geo features disabled when it isn't configured or can't be read
+pub fn init(path: Option<&str>) {
+    GEOIP.get_or_init(|| {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                tracing::info!(
+                    "GEOIP_DB_PATH not set, geo routing and country analytics are disabled"
+                );
+                return None;
+            }
+        };
+
+        match Reader::open_readfile(path) {
+            Ok(reader) => {
+                tracing::debug!("Loaded GeoIP database from {}", path);
+                Some(reader)
+            }
+            Err(err) => {
+                tracing::error!("Failed to load GeoIP database from {}: {:?}", path, err);
+                None
+            }
+        }
+    });
+}
+
+/// ISO 3166-1 alpha-2 code of the country the address belongs to
+pub fn get_country_code(ip: IpAddr) -> Option<String> {
+    let reader = GEOIP.get()?.as_ref()?;
+
+    let country = reader.lookup::<geoip2::Country>(ip).ok()?;
+    country
+        .country
+        .and_then(|country| country.iso_code)
+        .map(|code| code.to_string())
+}
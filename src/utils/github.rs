@@ -0,0 +1,218 @@
+use anyhow::Context;
+use askama::filters::urlencode;
+use axum::async_trait;
+use tracing::Instrument;
+
+use crate::{
+    config::GithubConfig,
+    utils::oauth::{Identity, LoginError, LoginFlow, LoginProvider},
+};
+
+const API_URL: &str = "https://api.github.com";
+/// GitHub rejects API requests without one
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+pub struct GithubUser {
+    pub id: i64,
+    pub login: String,
+    pub orgs: Vec<PartialOrg>,
+    pub teams: Vec<PartialTeam>,
+}
+
+impl GithubUser {
+    /// GitHub logins are case insensitive
+    pub fn has_org(&self, org: &str) -> bool {
+        self.orgs
+            .iter()
+            .any(|member_of| member_of.login.eq_ignore_ascii_case(org))
+    }
+
+    pub fn has_any_org(&self, orgs: &[String]) -> bool {
+        orgs.iter().any(|org| self.has_org(org))
+    }
+
+    /// `team` is `<org>/<team-slug>`
+    pub fn has_team(&self, team: &str) -> bool {
+        self.teams.iter().any(|member_of| {
+            format!("{}/{}", member_of.organization.login, member_of.slug)
+                .eq_ignore_ascii_case(team)
+        })
+    }
+
+    pub fn has_any_team(&self, teams: &[String]) -> bool {
+        teams.iter().any(|team| self.has_team(team))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct PartialGithubUser {
+    pub id: i64,
+    pub login: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct PartialOrg {
+    pub login: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct PartialTeam {
+    pub slug: String,
+    pub organization: PartialOrg,
+}
+
+/// GitHub answers a bad code with 200 and an `error` instead of an `access_token`
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error_description: Option<String>,
+}
+
+async fn get<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    token: &str,
+    path: &str,
+) -> anyhow::Result<T> {
+    client
+        .get(format!("{}{}", API_URL, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .with_context(|| format!("failed to fetch {} from github", path))?
+        .error_for_status()
+        .with_context(|| format!("failed to fetch {} from github", path))?
+        .json::<T>()
+        .await
+        .with_context(|| format!("failed to parse {} into structs", path))
+}
+
+/// The user behind a token, with their organizations and teams when `memberships` is set
+/// Memberships need the `read:org` scope, without it private ones are missing
+#[tracing::instrument(name = "github.get_user_info", skip_all, fields(otel.kind = "client"))]
+pub async fn get_user_info_by_token(token: &str, memberships: bool) -> anyhow::Result<GithubUser> {
+    let client = reqwest::Client::new();
+
+    let user_info = get::<PartialGithubUser>(&client, token, "/user").await?;
+    let (orgs, teams) = match memberships {
+        true => (
+            get::<Vec<PartialOrg>>(&client, token, "/user/orgs?per_page=100").await?,
+            get::<Vec<PartialTeam>>(&client, token, "/user/teams?per_page=100").await?,
+        ),
+        false => (vec![], vec![]),
+    };
+
+    Ok(GithubUser {
+        id: user_info.id,
+        login: user_info.login,
+        orgs,
+        teams,
+    })
+}
+
+/// Log in with a GitHub OAuth app, limited to members of the allowed organizations and teams when there are any
+pub struct GithubProvider {
+    config: GithubConfig,
+}
+
+impl GithubProvider {
+    pub fn new(config: GithubConfig) -> GithubProvider {
+        GithubProvider { config }
+    }
+
+    fn gated(&self) -> bool {
+        !self.config.orgs.is_empty() || !self.config.teams.is_empty()
+    }
+
+    async fn exchange_code(&self, code: &str, redirect_uri: &str) -> anyhow::Result<String> {
+        let res = reqwest::Client::new()
+            .post("https://github.com/login/oauth/access_token")
+            .header("Accept", "application/json")
+            .form(&[
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("code", code),
+            ])
+            .send()
+            .instrument(tracing::info_span!(
+                "github.exchange_code",
+                otel.kind = "client"
+            ))
+            .await
+            .context("failed to reach github")?
+            .error_for_status()
+            .context("github refused the code")?
+            .json::<TokenResponse>()
+            .await
+            .context("failed to parse token response")?;
+
+        res.access_token.ok_or_else(|| {
+            anyhow::anyhow!(
+                "github refused the code: {}",
+                res.error_description.unwrap_or_default()
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl LoginProvider for GithubProvider {
+    fn id(&self) -> &'static str {
+        "github"
+    }
+
+    fn display_name(&self) -> &str {
+        "GitHub"
+    }
+
+    async fn authorize_url(&self, flow: &LoginFlow, redirect_uri: &str) -> anyhow::Result<String> {
+        // memberships are only needed, and only asked for, when they decide who gets in
+        let scope = match self.gated() {
+            true => "read:user%20read:org",
+            false => "read:user",
+        };
+
+        Ok(format!(
+            "https://github.com/login/oauth/authorize?client_id={}&redirect_uri={}&scope={}&state={}",
+            self.config.client_id,
+            urlencode(redirect_uri)?,
+            scope,
+            flow.state,
+        ))
+    }
+
+    async fn identify(
+        &self,
+        code: &str,
+        _flow: &LoginFlow,
+        redirect_uri: &str,
+    ) -> Result<Identity, LoginError> {
+        let access_token = self
+            .exchange_code(code, redirect_uri)
+            .await
+            .map_err(LoginError::TokenExchange)?;
+
+        let user_info = get_user_info_by_token(&access_token, self.gated())
+            .await
+            .map_err(LoginError::UserInfo)?;
+
+        if self.gated()
+            && !user_info.has_any_org(&self.config.orgs)
+            && !user_info.has_any_team(&self.config.teams)
+        {
+            return Err(LoginError::Forbidden {
+                reason: "org",
+                message: "You are not a member of an allowed GitHub organization or team"
+                    .to_string(),
+            });
+        }
+
+        Ok(Identity {
+            subject: user_info.id.to_string(),
+            username: user_info.login,
+            role: None,
+        })
+    }
+}
@@ -0,0 +1,412 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use super::strings;
+
+/// The export formats links can be imported from
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// `key,url,host` rows, with an optional header row and an optional host column
+    Csv,
+    /// An array of shidou redirects, as returned by `GET /api/redirect`
+    Json,
+    /// A YOURLS CSV export with `keyword` and `url` columns
+    Yourls,
+    /// A Bitly CSV export or a `{ "links": [...] }` response of the Bitly API
+    Bitly,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ImportFormat::Csv),
+            "json" => Ok(ImportFormat::Json),
+            "yourls" => Ok(ImportFormat::Yourls),
+            "bitly" => Ok(ImportFormat::Bitly),
+            _ => Err(format!("Unsupported import format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportRow {
+    pub key: String,
+    pub url: String,
+    pub host: Option<String>,
+}
+
+/// A row of the input along with its position, so errors can point back at the source
+#[derive(Debug)]
+pub struct ParsedRow {
+    pub row: usize,
+    pub result: Result<ImportRow, String>,
+}
+
+/// Split an export into rows, validating each one on its own
+/// Only a malformed document as a whole is an error, problems with single rows are kept per row
+pub fn parse(input: &str, format: ImportFormat) -> anyhow::Result<Vec<ParsedRow>> {
+    let rows = match format {
+        ImportFormat::Csv => parse_csv(input)?,
+        ImportFormat::Json => parse_json(input)?,
+        ImportFormat::Yourls => parse_csv_with_headers(input, &["keyword"], &["url"])?,
+        ImportFormat::Bitly if input.trim_start().starts_with('{') => parse_bitly_json(input)?,
+        ImportFormat::Bitly => parse_csv_with_headers(
+            input,
+            &["link", "bitlink", "shortlink", "shorturl", "id"],
+            &["longurl"],
+        )?,
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|parsed| ParsedRow {
+            row: parsed.row,
+            result: parsed.result.and_then(validate_row),
+        })
+        .collect())
+}
+
+fn validate_row(row: ImportRow) -> Result<ImportRow, String> {
+    strings::validate_key(&row.key)?;
+
+    let url = strings::normalize_url(row.url.trim());
+    if reqwest::Url::parse(&url).is_err() {
+        return Err(format!("Invalid url: {}", row.url));
+    }
+
+    Ok(ImportRow {
+        key: row.key,
+        url,
+        host: row.host.filter(|host| !host.is_empty()),
+    })
+}
+
+fn csv_reader(input: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes())
+}
+
+fn parse_csv(input: &str) -> anyhow::Result<Vec<ParsedRow>> {
+    let mut rows = vec![];
+
+    for (i, record) in csv_reader(input).records().enumerate() {
+        let record = record.context("Failed to read CSV")?;
+        let row = record
+            .position()
+            .map(|p| p.line() as usize)
+            .unwrap_or(i + 1);
+
+        // the header row is optional
+        if i == 0 && record.get(0).is_some_and(|c| c.eq_ignore_ascii_case("key")) {
+            continue;
+        }
+
+        let result = match (record.get(0), record.get(1)) {
+            (Some(key), Some(url)) if !key.is_empty() && !url.is_empty() => Ok(ImportRow {
+                key: key.to_string(),
+                url: url.to_string(),
+                host: record.get(2).map(|host| host.to_string()),
+            }),
+            _ => Err("Expected at least a key and a url column".to_string()),
+        };
+        rows.push(ParsedRow { row, result });
+    }
+
+    Ok(rows)
+}
+
+/// Normalize a header so `Long URL`, `long_url` and `longUrl` all compare equal
+fn normalize_header(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Parse a CSV export of another shortener, locating the key and url columns by their headers
+/// Keys given as full short links like `https://bit.ly/abc` are reduced to their last path segment
+fn parse_csv_with_headers(
+    input: &str,
+    key_headers: &[&str],
+    url_headers: &[&str],
+) -> anyhow::Result<Vec<ParsedRow>> {
+    let mut records = csv_reader(input).into_records();
+
+    let headers: Vec<String> = match records.next() {
+        Some(headers) => headers
+            .context("Failed to read CSV header")?
+            .iter()
+            .map(normalize_header)
+            .collect(),
+        None => return Ok(vec![]),
+    };
+    let find_column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+    let key_column = find_column(key_headers)
+        .with_context(|| format!("Missing key column, expected one of {:?}", key_headers))?;
+    let url_column = find_column(url_headers)
+        .with_context(|| format!("Missing url column, expected one of {:?}", url_headers))?;
+
+    let mut rows = vec![];
+    for (i, record) in records.enumerate() {
+        let record = record.context("Failed to read CSV")?;
+        let row = record
+            .position()
+            .map(|p| p.line() as usize)
+            .unwrap_or(i + 2);
+
+        let result = match (record.get(key_column), record.get(url_column)) {
+            (Some(key), Some(url)) if !key.is_empty() && !url.is_empty() => Ok(ImportRow {
+                key: short_link_key(key),
+                url: url.to_string(),
+                host: None,
+            }),
+            _ => Err("Missing key or url".to_string()),
+        };
+        rows.push(ParsedRow { row, result });
+    }
+
+    Ok(rows)
+}
+
+fn short_link_key(link: &str) -> String {
+    link.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(link)
+        .to_string()
+}
+
+#[derive(serde::Deserialize)]
+struct JsonRow {
+    key: String,
+    url: String,
+    #[serde(alias = "host")]
+    redirect_host: Option<String>,
+}
+
+fn parse_json(input: &str) -> anyhow::Result<Vec<ParsedRow>> {
+    let values: Vec<serde_json::Value> =
+        serde_json::from_str(input).context("Expected a JSON array of redirects")?;
+
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| ParsedRow {
+            row: i + 1,
+            result: serde_json::from_value::<JsonRow>(value)
+                .map(|row| ImportRow {
+                    key: row.key,
+                    url: row.url,
+                    host: row.redirect_host,
+                })
+                .map_err(|err| err.to_string()),
+        })
+        .collect())
+}
+
+#[derive(serde::Deserialize)]
+struct BitlyLinks {
+    links: Vec<serde_json::Value>,
+}
+
+#[derive(serde::Deserialize)]
+struct BitlyLink {
+    link: String,
+    long_url: String,
+}
+
+fn parse_bitly_json(input: &str) -> anyhow::Result<Vec<ParsedRow>> {
+    let export: BitlyLinks =
+        serde_json::from_str(input).context("Expected a Bitly `links` response")?;
+
+    Ok(export
+        .links
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| ParsedRow {
+            row: i + 1,
+            result: serde_json::from_value::<BitlyLink>(value)
+                .map(|link| ImportRow {
+                    key: short_link_key(&link.link),
+                    url: link.long_url,
+                    host: None,
+                })
+                .map_err(|err| err.to_string()),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, short_link_key, ImportFormat, ImportRow, ParsedRow};
+
+    fn imported(rows: &[ParsedRow]) -> Vec<(usize, &str, &str, Option<&str>)> {
+        rows.iter()
+            .filter_map(|parsed| {
+                parsed.result.as_ref().ok().map(|row: &ImportRow| {
+                    (
+                        parsed.row,
+                        row.key.as_str(),
+                        row.url.as_str(),
+                        row.host.as_deref(),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn failed(rows: &[ParsedRow]) -> Vec<(usize, &str)> {
+        rows.iter()
+            .filter_map(|parsed| {
+                parsed
+                    .result
+                    .as_ref()
+                    .err()
+                    .map(|err| (parsed.row, err.as_str()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn csv_with_header_and_optional_host() {
+        let input = "key,url,host\n\
+                     docs,https://docs.example.com,go.example.com\n\
+                     blog, example.com/blog \n";
+        let rows = parse(input, ImportFormat::Csv).unwrap();
+
+        assert_eq!(
+            imported(&rows),
+            vec![
+                (
+                    2,
+                    "docs",
+                    "https://docs.example.com",
+                    Some("go.example.com")
+                ),
+                (3, "blog", "http://example.com/blog", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_without_header() {
+        let rows = parse("docs,https://docs.example.com\n", ImportFormat::Csv).unwrap();
+
+        assert_eq!(
+            imported(&rows),
+            vec![(1, "docs", "https://docs.example.com", None)]
+        );
+    }
+
+    #[test]
+    fn json_of_shidou_redirects() {
+        let input = r#"[
+            { "id": 1, "key": "docs", "url": "https://docs.example.com", "redirect_host": "go.example.com", "visits": 3 },
+            { "key": "blog", "url": "https://blog.example.com" },
+            { "key": "nourl" }
+        ]"#;
+        let rows = parse(input, ImportFormat::Json).unwrap();
+
+        assert_eq!(
+            imported(&rows),
+            vec![
+                (
+                    1,
+                    "docs",
+                    "https://docs.example.com",
+                    Some("go.example.com")
+                ),
+                (2, "blog", "https://blog.example.com", None),
+            ]
+        );
+        assert_eq!(failed(&rows).len(), 1);
+        assert_eq!(failed(&rows)[0].0, 3);
+    }
+
+    #[test]
+    fn yourls_csv() {
+        let input = "keyword,url,title,timestamp,ip,clicks\n\
+                     docs,https://docs.example.com,Docs,2024-01-01 00:00:00,127.0.0.1,12\n";
+        let rows = parse(input, ImportFormat::Yourls).unwrap();
+
+        assert_eq!(
+            imported(&rows),
+            vec![(2, "docs", "https://docs.example.com", None)]
+        );
+    }
+
+    #[test]
+    fn bitly_csv_and_json() {
+        let csv = "Bitlink,Long URL,Title\n\
+                   https://bit.ly/3abcDEF,https://docs.example.com,Docs\n";
+        let rows = parse(csv, ImportFormat::Bitly).unwrap();
+        assert_eq!(
+            imported(&rows),
+            vec![(2, "3abcDEF", "https://docs.example.com", None)]
+        );
+
+        let json = r#"{ "links": [
+            { "link": "https://bit.ly/3abcDEF", "long_url": "https://docs.example.com", "id": "bit.ly/3abcDEF" },
+            { "link": "https://bit.ly/broken" }
+        ] }"#;
+        let rows = parse(json, ImportFormat::Bitly).unwrap();
+        assert_eq!(
+            imported(&rows),
+            vec![(1, "3abcDEF", "https://docs.example.com", None)]
+        );
+        assert_eq!(failed(&rows).len(), 1);
+    }
+
+    #[test]
+    fn missing_columns_fail_the_whole_document() {
+        assert!(parse(
+            "title,url\nDocs,https://docs.example.com\n",
+            ImportFormat::Yourls
+        )
+        .is_err());
+        assert!(parse("{ \"data\": [] }", ImportFormat::Json).is_err());
+    }
+
+    #[test]
+    fn invalid_rows_fail_on_their_own() {
+        let input = "key,url\n\
+                     docs,https://docs.example.com\n\
+                     api,https://api.example.com\n\
+                     docs+,https://docs.example.com\n\
+                     has space,https://docs.example.com\n\
+                     lonely\n";
+        let rows = parse(input, ImportFormat::Csv).unwrap();
+
+        assert_eq!(
+            imported(&rows),
+            vec![(2, "docs", "https://docs.example.com", None)]
+        );
+        assert_eq!(
+            failed(&rows),
+            vec![
+                (3, "Key is reserved: api"),
+                (4, "Key is reserved: docs+"),
+                (
+                    5,
+                    "Key contains characters not allowed in a path: has space"
+                ),
+                (6, "Expected at least a key and a url column"),
+            ]
+        );
+    }
+
+    #[test]
+    fn short_link_keys() {
+        assert_eq!(short_link_key("https://bit.ly/abc"), "abc");
+        assert_eq!(short_link_key("https://bit.ly/abc/"), "abc");
+        assert_eq!(short_link_key("abc"), "abc");
+    }
+}
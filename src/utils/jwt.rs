@@ -1,22 +1,31 @@
+use std::time::Duration;
+
 use anyhow::Context;
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 
-use super::env;
-
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct JWT {
     pub user_id: i64,
+    /// The row in `sessions` the token belongs to, revoking it invalidates the token
+    pub sid: String,
     pub exp: usize,
 }
 
-pub fn create_jwt(user_id: i64) -> anyhow::Result<String> {
-    let enc_key = EncodingKey::from_ed_pem(&env::get_jwt_private())
-        .context("Failed to create JWT private key")?;
-
-    let exp = (Utc::now() + Duration::try_days(30).unwrap()).timestamp() as usize;
-    let jwt = JWT { user_id, exp };
-    let token = encode(&Header::new(Algorithm::EdDSA), &jwt, &enc_key)
+/// Issue an access token for a session, valid for `ttl`
+pub fn create_jwt(
+    user_id: i64,
+    sid: &str,
+    ttl: Duration,
+    enc_key: &EncodingKey,
+) -> anyhow::Result<String> {
+    let exp = (Utc::now().timestamp() as u64 + ttl.as_secs()) as usize;
+    let jwt = JWT {
+        user_id,
+        sid: sid.to_string(),
+        exp,
+    };
+    let token = encode(&Header::new(Algorithm::EdDSA), &jwt, enc_key)
         .context("Failed to create new JWT")?;
 
     Ok(token)
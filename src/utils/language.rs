@@ -0,0 +1,125 @@
+use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap};
+
+/// Parse an `Accept-Language` header into language ranges ordered by descending quality
+/// Ranges with a quality of 0 are not acceptable and are left out
+pub fn parse_accept_language(header: &str) -> Vec<(String, f32)> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            if tag.is_empty() {
+                return None;
+            }
+
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            Some((tag, quality.clamp(0.0, 1.0)))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    // stable sort keeps the header order for ranges with the same quality
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges
+}
+
+/// Pick the best of the available language tags for the client's `Accept-Language` header
+/// Each range is tried as is and then with its subtags removed, so `de-AT` falls back to `de`
+pub fn negotiate<'a>(headers: &HeaderMap, available: &[&'a str]) -> Option<&'a str> {
+    let header = headers.get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+
+    for (range, _) in parse_accept_language(header) {
+        if range == "*" {
+            continue;
+        }
+
+        let mut candidate = range.as_str();
+        loop {
+            if let Some(tag) = available
+                .iter()
+                .find(|tag| tag.eq_ignore_ascii_case(candidate))
+            {
+                return Some(tag);
+            }
+
+            match candidate.rfind('-') {
+                Some(i) => candidate = &candidate[..i],
+                None => break,
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap, HeaderValue};
+
+    use super::{negotiate, parse_accept_language};
+
+    fn accept_language(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn orders_by_quality_then_header_order() {
+        assert_eq!(
+            parse_accept_language("de;q=0.5, EN-us, fr;q=0.9, es;q=0.5"),
+            vec![
+                ("en-us".to_string(), 1.0),
+                ("fr".to_string(), 0.9),
+                ("de".to_string(), 0.5),
+                ("es".to_string(), 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_unacceptable_and_broken_ranges() {
+        assert_eq!(
+            parse_accept_language("fr;q=0, de;q=abc, , en;q=2"),
+            vec![("en".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn negotiates_by_quality() {
+        let headers = accept_language("de;q=0.8, fr");
+        assert_eq!(negotiate(&headers, &["de", "fr"]), Some("fr"));
+    }
+
+    #[test]
+    fn skips_languages_with_quality_zero() {
+        let headers = accept_language("fr;q=0, en;q=0.1");
+        assert_eq!(negotiate(&headers, &["fr", "en"]), Some("en"));
+    }
+
+    #[test]
+    fn falls_back_from_region_to_language() {
+        let headers = accept_language("en-US, fr;q=0.9");
+        assert_eq!(negotiate(&headers, &["fr", "en"]), Some("en"));
+        // an exact match with the region wins over the bare language
+        assert_eq!(negotiate(&headers, &["en", "en-us"]), Some("en-us"));
+    }
+
+    #[test]
+    fn wildcard_picks_nothing_on_its_own() {
+        assert_eq!(negotiate(&accept_language("*"), &["en", "fr"]), None);
+        assert_eq!(
+            negotiate(&accept_language("*, fr;q=0.5"), &["en", "fr"]),
+            Some("fr")
+        );
+    }
+
+    #[test]
+    fn no_header_no_language() {
+        assert_eq!(negotiate(&HeaderMap::new(), &["en"]), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use chrono::{SecondsFormat, Utc};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    field::MakeExt,
+    fmt::{
+        format::{self, Writer},
+        FmtContext, FormatEvent, FormatFields, FormattedFields,
+    },
+    registry::LookupSpan,
+};
+
+/// Writes `ts=... level=... target=...` followed by the fields of the enclosing spans and the event
+pub struct Logfmt;
+
+impl<S, N> FormatEvent<S, N> for Logfmt
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        write!(
+            writer,
+            "ts={} level={} target={}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            metadata.level().to_string().to_lowercase(),
+            metadata.target()
+        )?;
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, " {}", fields)?;
+                    }
+                }
+            }
+        }
+
+        writer.write_char(' ')?;
+        ctx.format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Field formatter to pair with `Logfmt`, the message is written as `msg`
+pub fn fields() -> impl for<'writer> FormatFields<'writer> + 'static {
+    format::debug_fn(|writer, field, value| {
+        let value = format!("{:?}", value);
+        match field.name() {
+            "message" => write!(writer, "msg={:?}", value),
+            name => write!(writer, "{}={}", name, quote(&value)),
+        }
+    })
+    .delimited(" ")
+}
+
+/// Quote values that would otherwise break up the line, strings recorded as `&str` come quoted already
+fn quote(value: &str) -> String {
+    let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+    match quoted {
+        false if value.is_empty() || value.contains([' ', '=', '"']) => format!("{:?}", value),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote;
+
+    #[test]
+    fn leaves_plain_values_bare() {
+        assert_eq!(quote("200"), "200");
+        assert_eq!(quote("/api/redirect"), "/api/redirect");
+    }
+
+    #[test]
+    fn quotes_values_that_would_break_the_line() {
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote("GET /abc"), r#""GET /abc""#);
+        assert_eq!(quote("a=b"), r#""a=b""#);
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+    }
+
+    #[test]
+    fn keeps_values_that_come_quoted() {
+        assert_eq!(quote(r#""already quoted""#), r#""already quoted""#);
+    }
+}
@@ -1,4 +1,14 @@
+pub mod client_ip;
 pub mod discord;
-pub mod env;
+pub mod forwarded;
+pub mod geoip;
+pub mod github;
+pub mod import;
 pub mod jwt;
+pub mod language;
+pub mod logfmt;
+pub mod oauth;
+pub mod oidc;
+pub mod qr;
 pub mod strings;
+pub mod user_agent;
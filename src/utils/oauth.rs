@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::Config,
+    utils::{
+        discord::DiscordProvider, github::GithubProvider, oidc::OidcProvider,
+        strings::generate_random_string,
+    },
+};
+
+const STATE_LENGTH: usize = 32;
+/// RFC 7636 wants 43 to 128 characters
+const PKCE_VERIFIER_LENGTH: usize = 64;
+
+/// The account someone logged in with, as the provider knows it
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// Stable id of the account at the provider, like the Discord snowflake or the OIDC `sub`
+    pub subject: String,
+    pub username: String,
+    /// Role the provider decided on, replacing the stored one, `None` leaves roles to the CLI
+    pub role: Option<&'static str>,
+}
+
+/// Why a login was turned down, `reason` labels the failure in `shidou_logins_total`
+#[derive(Debug)]
+pub enum LoginError {
+    /// The provider wouldn't trade the code for tokens
+    TokenExchange(anyhow::Error),
+    /// The tokens didn't lead to a valid account
+    UserInfo(anyhow::Error),
+    /// The account is fine but not allowed to log in here
+    Forbidden {
+        reason: &'static str,
+        message: String,
+    },
+}
+
+impl LoginError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LoginError::TokenExchange(_) => "token_exchange",
+            LoginError::UserInfo(_) => "user_info",
+            LoginError::Forbidden { reason, .. } => reason,
+        }
+    }
+}
+
+/// What a login started with, kept in an encrypted cookie until the provider sends the browser back
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct LoginFlow {
+    /// `id` of the provider the login was started with
+    pub provider: String,
+    /// Echoed back by the provider, a callback without the matching value wasn't started by this browser
+    pub state: String,
+    /// PKCE secret, only its hash is sent with the authorization request
+    pub verifier: String,
+    /// Put into the `id_token` by OpenID Connect providers, so a token can't be replayed into another login
+    pub nonce: String,
+}
+
+impl LoginFlow {
+    pub fn new(provider: &str) -> LoginFlow {
+        LoginFlow {
+            provider: provider.to_string(),
+            state: generate_random_string(STATE_LENGTH),
+            verifier: generate_random_string(PKCE_VERIFIER_LENGTH),
+            nonce: generate_random_string(STATE_LENGTH),
+        }
+    }
+
+    /// The S256 `code_challenge` of the verifier
+    pub fn challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.verifier.as_bytes()))
+    }
+}
+
+/// Somewhere users can log in with, the callback is the same for all of them
+#[async_trait]
+pub trait LoginProvider: Send + Sync {
+    /// Name in login urls and the `identities` table, changing it orphans the accounts linked to it
+    fn id(&self) -> &'static str;
+    /// Shown on the login button
+    fn display_name(&self) -> &str;
+    /// Where to send the browser to log in
+    async fn authorize_url(&self, flow: &LoginFlow, redirect_uri: &str) -> anyhow::Result<String>;
+    /// Trade the code of the callback for the account that logged in, checking that it may log in here
+    async fn identify(
+        &self,
+        code: &str,
+        flow: &LoginFlow,
+        redirect_uri: &str,
+    ) -> Result<Identity, LoginError>;
+}
+
+/// The providers enabled in the config, in the order their login buttons are shown
+pub struct LoginProviders {
+    providers: Vec<Arc<dyn LoginProvider>>,
+}
+
+impl LoginProviders {
+    pub fn from_config(config: &Config) -> LoginProviders {
+        let mut providers: Vec<Arc<dyn LoginProvider>> = vec![];
+        if let Some(discord) = &config.discord {
+            providers.push(Arc::new(DiscordProvider::new(discord.clone())));
+        }
+        if let Some(github) = &config.github {
+            providers.push(Arc::new(GithubProvider::new(github.clone())));
+        }
+        if let Some(oidc) = &config.oidc {
+            providers.push(Arc::new(OidcProvider::new(oidc.clone())));
+        }
+
+        LoginProviders { providers }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<dyn LoginProvider>> {
+        self.providers
+            .iter()
+            .find(|provider| provider.id() == id)
+            .cloned()
+    }
+
+    /// The provider `/auth/login` picks when none is asked for
+    pub fn default_provider(&self) -> Option<Arc<dyn LoginProvider>> {
+        self.providers.first().cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn LoginProvider>> {
+        self.providers.iter()
+    }
+}
@@ -0,0 +1,269 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use axum::async_trait;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use tokio::sync::{Mutex, OnceCell};
+use tracing::Instrument;
+
+use crate::{
+    config::OidcConfig,
+    utils::oauth::{Identity, LoginError, LoginFlow, LoginProvider},
+};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+/// Signing keys are fetched again after this, or right away when a token names a key we don't know
+const JWKS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// Asymmetric algorithms only, an `HS256` token could be signed by anyone who knows the client secret
+const ALLOWED_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The parts of the discovery document the login needs
+#[derive(Debug, serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+    email: Option<String>,
+    name: Option<String>,
+}
+
+/// Log in with any OpenID Connect provider, using the authorization code flow with PKCE
+/// The discovery document is fetched on the first login, so an unreachable issuer doesn't keep shidou from starting
+pub struct OidcProvider {
+    config: OidcConfig,
+    client: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: Mutex<Option<(JwkSet, Instant)>>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcConfig) -> OidcProvider {
+        OidcProvider {
+            config,
+            client: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+            jwks: Mutex::new(None),
+        }
+    }
+
+    async fn metadata(&self) -> anyhow::Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!("{}{}", self.config.issuer, DISCOVERY_PATH);
+                let metadata = self
+                    .client
+                    .get(&url)
+                    .send()
+                    .instrument(tracing::info_span!("oidc.discovery", otel.kind = "client"))
+                    .await
+                    .with_context(|| format!("failed to fetch {}", url))?
+                    .error_for_status()
+                    .with_context(|| format!("failed to fetch {}", url))?
+                    .json::<ProviderMetadata>()
+                    .await
+                    .context("failed to parse discovery document")?;
+
+                // a document claiming another issuer would let that issuer's tokens in
+                if metadata.issuer.trim_end_matches('/') != self.config.issuer {
+                    return Err(anyhow::anyhow!(
+                        "discovery document is for issuer {}, not {}",
+                        metadata.issuer,
+                        self.config.issuer
+                    ));
+                }
+
+                Ok(metadata)
+            })
+            .await
+    }
+
+    async fn fetch_jwks(&self) -> anyhow::Result<JwkSet> {
+        let metadata = self.metadata().await?;
+        self.client
+            .get(&metadata.jwks_uri)
+            .send()
+            .instrument(tracing::info_span!("oidc.jwks", otel.kind = "client"))
+            .await
+            .context("failed to fetch signing keys")?
+            .error_for_status()
+            .context("failed to fetch signing keys")?
+            .json::<JwkSet>()
+            .await
+            .context("failed to parse signing keys")
+    }
+
+    /// The key a token was signed with, fetching the keys again once if it's not among the cached ones
+    async fn decoding_key(&self, kid: Option<&str>) -> anyhow::Result<DecodingKey> {
+        let mut jwks = self.jwks.lock().await;
+
+        for refetch in [false, true] {
+            let stale = match &*jwks {
+                Some((_, fetched)) => fetched.elapsed() > JWKS_MAX_AGE,
+                None => true,
+            };
+            if refetch || stale {
+                *jwks = Some((self.fetch_jwks().await?, Instant::now()));
+            }
+
+            if let Some((keys, _)) = &*jwks {
+                let jwk = match kid {
+                    Some(kid) => keys.find(kid),
+                    // without a `kid` the token can only be matched to a lone key
+                    None if keys.keys.len() == 1 => keys.keys.first(),
+                    None => None,
+                };
+                if let Some(jwk) = jwk {
+                    return DecodingKey::from_jwk(jwk).context("unusable signing key");
+                }
+            }
+        }
+
+        Err(anyhow::anyhow!("no signing key with id {:?}", kid))
+    }
+
+    /// Check the signature, issuer, audience, expiry and nonce of an `id_token`
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> anyhow::Result<IdTokenClaims> {
+        let header = decode_header(id_token).context("malformed id_token")?;
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(anyhow::anyhow!(
+                "id_token is signed with {:?}, which is not allowed",
+                header.alg
+            ));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer, &format!("{}/", self.config.issuer)]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .context("invalid id_token")?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(anyhow::anyhow!("id_token was issued for another login"));
+        }
+
+        Ok(claims)
+    }
+}
+
+#[async_trait]
+impl LoginProvider for OidcProvider {
+    fn id(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn display_name(&self) -> &str {
+        &self.config.display_name
+    }
+
+    async fn authorize_url(&self, flow: &LoginFlow, redirect_uri: &str) -> anyhow::Result<String> {
+        let metadata = self.metadata().await?;
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", redirect_uri),
+                ("scope", &self.config.scopes.join(" ")),
+                ("state", &flow.state),
+                ("nonce", &flow.nonce),
+                ("code_challenge", &flow.challenge()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .context("invalid authorization_endpoint")?;
+
+        Ok(url.to_string())
+    }
+
+    async fn identify(
+        &self,
+        code: &str,
+        flow: &LoginFlow,
+        redirect_uri: &str,
+    ) -> Result<Identity, LoginError> {
+        let metadata = self.metadata().await.map_err(LoginError::TokenExchange)?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &flow.verifier),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let res = self
+            .client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .instrument(tracing::info_span!(
+                "oidc.exchange_code",
+                otel.kind = "client"
+            ))
+            .await
+            .context("failed to reach the token endpoint")
+            .map_err(LoginError::TokenExchange)?;
+        if !res.status().is_success() {
+            let err_text = res.text().await.unwrap_or_default();
+            return Err(LoginError::TokenExchange(anyhow::anyhow!(
+                "issuer refused the code: {}",
+                err_text
+            )));
+        }
+        let token_response = res
+            .json::<TokenResponse>()
+            .await
+            .context("failed to parse token response")
+            .map_err(LoginError::TokenExchange)?;
+
+        let claims = self
+            .validate_id_token(&token_response.id_token, &flow.nonce)
+            .await
+            .map_err(LoginError::UserInfo)?;
+
+        let username = claims
+            .preferred_username
+            .or(claims.email)
+            .or(claims.name)
+            .unwrap_or_else(|| claims.sub.clone());
+
+        Ok(Identity {
+            subject: claims.sub,
+            username,
+            role: None,
+        })
+    }
+}
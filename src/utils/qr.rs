@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use qrcode::{Color, EcLevel, QrCode};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QrFormat {
+    Svg,
+    Png,
+}
+
+impl QrFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            QrFormat::Svg => "image/svg+xml",
+            QrFormat::Png => "image/png",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(u8, u8, u8);
+
+impl Rgb {
+    /// Parse a hex color like `#1e293b`, `1e293b` or `fff`
+    pub fn parse(s: &str) -> Option<Rgb> {
+        let hex = s.trim_start_matches('#');
+        // the slicing below is by byte, anything but ASCII hex digits could split a character
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let hex = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+            6 => hex.to_string(),
+            _ => return None,
+        };
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+        Some(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QrOptions {
+    pub format: QrFormat,
+    /// Requested width and height of the image in pixels
+    pub size: u32,
+    /// Width of the quiet zone around the code, in modules
+    pub margin: u32,
+    pub ec_level: EcLevel,
+    pub foreground: Rgb,
+    pub background: Rgb,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        QrOptions {
+            format: QrFormat::Svg,
+            size: 256,
+            margin: 4,
+            ec_level: EcLevel::M,
+            foreground: Rgb(0, 0, 0),
+            background: Rgb(255, 255, 255),
+        }
+    }
+}
+
+impl QrOptions {
+    const MIN_SIZE: u32 = 64;
+    const MAX_SIZE: u32 = 2048;
+    const MAX_MARGIN: u32 = 16;
+
+    /// Build the render options from the query parameters of a QR request
+    /// Supported parameters are `format` (svg, png), `size`, `margin`, `ec` (L, M, Q, H), `fg` and `bg`
+    pub fn from_params(params: &HashMap<String, String>) -> Result<QrOptions, String> {
+        let mut options = QrOptions::default();
+
+        if let Some(format) = params.get("format") {
+            options.format = match format.to_ascii_lowercase().as_str() {
+                "svg" => QrFormat::Svg,
+                "png" => QrFormat::Png,
+                _ => return Err(format!("Unsupported QR format: {}", format)),
+            };
+        }
+        if let Some(size) = params.get("size") {
+            options.size = match size.parse::<u32>() {
+                Ok(size) if (Self::MIN_SIZE..=Self::MAX_SIZE).contains(&size) => size,
+                _ => {
+                    return Err(format!(
+                        "QR size must be between {} and {} pixels",
+                        Self::MIN_SIZE,
+                        Self::MAX_SIZE
+                    ))
+                }
+            };
+        }
+        if let Some(margin) = params.get("margin") {
+            options.margin = match margin.parse::<u32>() {
+                Ok(margin) if margin <= Self::MAX_MARGIN => margin,
+                _ => {
+                    return Err(format!(
+                        "QR margin must be between 0 and {} modules",
+                        Self::MAX_MARGIN
+                    ))
+                }
+            };
+        }
+        if let Some(ec) = params.get("ec") {
+            options.ec_level = match ec.to_ascii_uppercase().as_str() {
+                "L" => EcLevel::L,
+                "M" => EcLevel::M,
+                "Q" => EcLevel::Q,
+                "H" => EcLevel::H,
+                _ => return Err(format!("Unsupported QR error correction level: {}", ec)),
+            };
+        }
+        if let Some(fg) = params.get("fg") {
+            options.foreground =
+                Rgb::parse(fg).ok_or(format!("Invalid foreground color: {}", fg))?;
+        }
+        if let Some(bg) = params.get("bg") {
+            options.background =
+                Rgb::parse(bg).ok_or(format!("Invalid background color: {}", bg))?;
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn render(data: &str, options: &QrOptions) -> anyhow::Result<Vec<u8>> {
+    let code = QrCode::with_error_correction_level(data, options.ec_level)
+        .context("Failed to encode QR code")?;
+
+    match options.format {
+        QrFormat::Svg => Ok(render_svg(&code, options).into_bytes()),
+        QrFormat::Png => render_png(&code, options),
+    }
+}
+
+fn render_svg(code: &QrCode, options: &QrOptions) -> String {
+    let width = code.width() as u32;
+    let dimension = width + options.margin * 2;
+
+    let mut path = String::new();
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Dark {
+            let x = i as u32 % width + options.margin;
+            let y = i as u32 / width + options.margin;
+            path.push_str(&format!("M{},{}h1v1h-1z", x, y));
+        }
+    }
+
+    format!(
+        r#"<?xml version="1.0" standalone="yes"?><svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{size}" height="{size}" viewBox="0 0 {dimension} {dimension}" shape-rendering="crispEdges"><rect width="{dimension}" height="{dimension}" fill="{bg}"/><path d="{path}" fill="{fg}"/></svg>"#,
+        size = options.size,
+        dimension = dimension,
+        bg = options.background.to_hex(),
+        fg = options.foreground.to_hex(),
+        path = path,
+    )
+}
+
+fn render_png(code: &QrCode, options: &QrOptions) -> anyhow::Result<Vec<u8>> {
+    let width = code.width() as u32;
+    let dimension = width + options.margin * 2;
+    // modules are drawn as whole pixel squares so the code stays sharp
+    let scale = (options.size / dimension).max(1);
+    let pixels = dimension * scale;
+
+    let colors = code.to_colors();
+    let mut data = Vec::with_capacity((pixels * pixels * 3) as usize);
+    for py in 0..pixels {
+        for px in 0..pixels {
+            let (mx, my) = (px / scale, py / scale);
+            let dark = mx >= options.margin
+                && my >= options.margin
+                && mx < width + options.margin
+                && my < width + options.margin
+                && colors[((my - options.margin) * width + (mx - options.margin)) as usize]
+                    == Color::Dark;
+            let Rgb(r, g, b) = match dark {
+                true => options.foreground,
+                false => options.background,
+            };
+            data.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, pixels, pixels);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .context("Failed to write PNG header")?;
+    writer
+        .write_image_data(&data)
+        .context("Failed to write PNG data")?;
+    writer.finish().context("Failed to finish PNG")?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rgb;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(Rgb::parse("#1e293b"), Some(Rgb(0x1e, 0x29, 0x3b)));
+        assert_eq!(Rgb::parse("fff"), Some(Rgb(255, 255, 255)));
+    }
+
+    #[test]
+    fn rejects_non_hex_input() {
+        assert_eq!(Rgb::parse("€"), None);
+        assert_eq!(Rgb::parse("#ab€d"), None);
+        assert_eq!(Rgb::parse("+f+f+f"), None);
+        assert_eq!(Rgb::parse("12345"), None);
+    }
+}
@@ -1,35 +1,62 @@
-use axum::http::HeaderMap;
-use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+pub use shidou::random::generate_random_string;
 
-/// Generate a random string of a given length
-/// # Examples
-/// ```
-/// use url_shortener::utils::strings::generate_random_string;
-/// let rand_str = generate_random_string(5);
-/// assert_eq!(rand_str.len(), 5);
-/// ```
-pub fn generate_random_string(length: usize) -> String {
-    let rand_string: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
-        .take(length)
-        .map(char::from)
-        .collect();
+/// Compare two secrets in constant time, hashing them first keeps their lengths from leaking too
+pub fn secrets_match(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
 
-    rand_string
+/// Prefix a user supplied url with a scheme when it doesn't have one
+pub fn normalize_url(url: &str) -> String {
+    match url {
+        url if url.starts_with("http://") || url.starts_with("https://") => url.to_owned(),
+        _ => format!("http://{}", url),
+    }
 }
 
-pub fn get_host_header(headers: &HeaderMap, add_proto: bool) -> String {
-    let string = headers
-        .get("host")
-        .expect("host header not found")
-        .to_str()
-        .expect("host header couldn't be converted to string");
+/// Paths served by shidou itself, which can't be used as keys
+const RESERVED_KEYS: [&str; 10] = [
+    "api",
+    "auth",
+    "ui",
+    "assets",
+    "healthcheck",
+    "livez",
+    "readyz",
+    "metrics",
+    "favicon.ico",
+    "site.webmanifest",
+];
 
-    match add_proto {
-        false => string.to_string(),
-        true if string.contains("localhost") => "http://localhost:8080".to_string(),
-        true if string.starts_with("http") => string.to_string(),
-        true => format!("https://{}", string),
+/// Check that a key can be used as the path of a short link
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("Key must not be empty".to_string());
+    }
+    if key.chars().count() > 64 {
+        return Err(format!("Key is longer than 64 characters: {}", key));
+    }
+    if key
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '?' | '#' | '%'))
+    {
+        return Err(format!(
+            "Key contains characters not allowed in a path: {}",
+            key
+        ));
     }
+    // `key+` and `key.qr` are the preview page and QR code of `key`
+    if key.starts_with('/') || key.ends_with('+') || key.ends_with(".qr") {
+        return Err(format!("Key is reserved: {}", key));
+    }
+    let first_segment = key.split('/').next().unwrap_or(key);
+    if RESERVED_KEYS.contains(&first_segment) {
+        return Err(format!("Key is reserved: {}", key));
+    }
+
+    Ok(())
 }
@@ -0,0 +1,192 @@
+use axum::http::{header::USER_AGENT, HeaderMap};
+
+/// User-Agent fragments of the crawlers that chat apps and social networks use to unfurl links
+const UNFURL_BOTS: [&str; 9] = [
+    "discordbot",
+    "slackbot",
+    "slack-imgproxy",
+    "twitterbot",
+    "facebookexternalhit",
+    "linkedinbot",
+    "telegrambot",
+    "whatsapp",
+    "mastodon",
+];
+
+pub fn get_user_agent(headers: &HeaderMap) -> &str {
+    headers
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or_default()
+}
+
+pub fn is_unfurl_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+    UNFURL_BOTS.iter().any(|bot| user_agent.contains(bot))
+}
+
+/// User-Agent fragments of generic crawlers, scripts and headless browsers
+const GENERIC_BOTS: [&str; 10] = [
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "preview",
+    "headless",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "go-http-client",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Os {
+    Ios,
+    Android,
+    Windows,
+    Macos,
+    Linux,
+    Other,
+}
+
+impl Os {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Os::Ios => "ios",
+            Os::Android => "android",
+            Os::Windows => "windows",
+            Os::Macos => "macos",
+            Os::Linux => "linux",
+            Os::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    Mobile,
+    Tablet,
+    Desktop,
+}
+
+impl Device {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Device::Mobile => "mobile",
+            Device::Tablet => "tablet",
+            Device::Desktop => "desktop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UserAgentInfo {
+    pub os: Os,
+    pub device: Device,
+    pub bot: bool,
+}
+
+/// Roughly classify a User-Agent by operating system, device class and whether it is a bot
+pub fn parse(user_agent: &str) -> UserAgentInfo {
+    let ua = user_agent.to_ascii_lowercase();
+
+    let os = if ua.contains("iphone") || ua.contains("ipad") || ua.contains("ipod") {
+        Os::Ios
+    } else if ua.contains("android") {
+        Os::Android
+    } else if ua.contains("windows") {
+        Os::Windows
+    } else if ua.contains("macintosh") || ua.contains("mac os x") {
+        Os::Macos
+    } else if ua.contains("linux") || ua.contains("x11") || ua.contains("cros") {
+        Os::Linux
+    } else {
+        Os::Other
+    };
+
+    let device = if ua.contains("ipad") || ua.contains("tablet") {
+        Device::Tablet
+    } else if os == Os::Android && !ua.contains("mobile") {
+        // Android tablets leave `Mobile` out of their User-Agent
+        Device::Tablet
+    } else if ua.contains("mobi") || ua.contains("iphone") || ua.contains("ipod") {
+        Device::Mobile
+    } else {
+        Device::Desktop
+    };
+
+    let bot =
+        ua.is_empty() || is_unfurl_bot(&ua) || GENERIC_BOTS.iter().any(|bot| ua.contains(bot));
+
+    UserAgentInfo { os, device, bot }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Device, Os};
+
+    #[test]
+    fn classifies_real_user_agents() {
+        let cases = [
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1",
+                Os::Ios,
+                Device::Mobile,
+                false,
+            ),
+            (
+                "Mozilla/5.0 (iPad; CPU OS 12_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/12.1 Mobile/15E148 Safari/604.1",
+                Os::Ios,
+                Device::Tablet,
+                false,
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36",
+                Os::Android,
+                Device::Mobile,
+                false,
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 13; SM-X710) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+                Os::Android,
+                Device::Tablet,
+                false,
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36 Edg/124.0.0.0",
+                Os::Windows,
+                Device::Desktop,
+                false,
+            ),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+                Os::Macos,
+                Device::Desktop,
+                false,
+            ),
+            (
+                "Mozilla/5.0 (X11; CrOS x86_64 14541.0.0) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+                Os::Linux,
+                Device::Desktop,
+                false,
+            ),
+            (
+                "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+                Os::Other,
+                Device::Desktop,
+                true,
+            ),
+            ("curl/8.5.0", Os::Other, Device::Desktop, true),
+            ("", Os::Other, Device::Desktop, true),
+        ];
+
+        for (user_agent, os, device, bot) in cases {
+            let info = parse(user_agent);
+            assert_eq!(info.os, os, "{}", user_agent);
+            assert_eq!(info.device, device, "{}", user_agent);
+            assert_eq!(info.bot, bot, "{}", user_agent);
+        }
+    }
+}